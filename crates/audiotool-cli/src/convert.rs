@@ -0,0 +1,415 @@
+//! Reporting for the `convert` command.
+
+use rmx::prelude::*;
+use rmx::serde::Serialize;
+use rmx::serde_json::{self, json};
+use rmx::indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use audiotool::convert::plan::Plan;
+use audiotool::convert::exec::{ConvertResult, Response};
+use audiotool::convert::{OutFileAction, SkipReason};
+use audiotool::codecs;
+use audiotool::io::Props;
+use audiotool::types::Format;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Print what a plan would do, without converting anything.
+pub fn print_dry_run(plan: &Plan, json: bool) -> AnyResult<()> {
+    if json {
+        print_dry_run_json(plan)
+    } else {
+        print_dry_run_table(plan);
+        Ok(())
+    }
+}
+
+fn print_dry_run_table(plan: &Plan) {
+    let header = [
+        S("ACTION"),
+        S("SOURCE"),
+        S("SOURCE FORMAT"),
+        S("TARGET FORMAT"),
+        S("OUTPUT"),
+    ];
+    let mut rows = vec![header];
+    let (mut create, mut overwrite, mut skip, mut failed) = (0, 0, 0, 0);
+
+    for infile_plan in &plan.outputs {
+        let props = match codecs::probe(&infile_plan.infile) {
+            Ok(props) => props_desc(props),
+            Err(e) => format!("error: {e}"),
+        };
+        for outfile in &infile_plan.outfiles {
+            if let Some(error) = outfile.error() {
+                failed += 1;
+                rows.push([
+                    S("error"),
+                    infile_plan.infile.display().to_string(),
+                    props.clone(),
+                    format_desc(outfile.format()),
+                    error.to_string(),
+                ]);
+                continue;
+            }
+            match outfile.action() {
+                OutFileAction::Create | OutFileAction::KeepBoth => create += 1,
+                OutFileAction::Overwrite => overwrite += 1,
+                OutFileAction::Skip(_) => skip += 1,
+            }
+            rows.push([
+                S(action_desc(outfile.action())),
+                infile_plan.infile.display().to_string(),
+                props.clone(),
+                format_desc(outfile.format()),
+                outfile.path().display().to_string(),
+            ]);
+        }
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in &rows {
+        let line = row.iter().zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    println!();
+    println!("{create} to create, {overwrite} to overwrite, {skip} to skip, {failed} with errors");
+}
+
+fn print_dry_run_json(plan: &Plan) -> AnyResult<()> {
+    let inputs: Vec<_> = plan.outputs.iter().map(|infile_plan| {
+        let (props, error) = match codecs::probe(&infile_plan.infile) {
+            Ok(props) => (Some(props), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        json!({
+            "infile": infile_plan.infile,
+            "props": props,
+            "error": error,
+            "outfiles": infile_plan.outfiles,
+        })
+    }).collect();
+
+    let json = serde_json::to_string_pretty(&inputs)?;
+    println!("{json}");
+
+    Ok(())
+}
+
+fn action_desc(action: OutFileAction) -> &'static str {
+    match action {
+        OutFileAction::Create => "create",
+        OutFileAction::Overwrite => "overwrite",
+        OutFileAction::KeepBoth => "create (keep both)",
+        OutFileAction::Skip(SkipReason::UpToDate) => "skip (up to date)",
+        OutFileAction::Skip(SkipReason::Unchanged) => "skip (unchanged)",
+        OutFileAction::Skip(SkipReason::Exists) => "skip (exists)",
+    }
+}
+
+fn format_desc(format: Format) -> String {
+    let bit_depth = format!("{:?}", format.bit_depth).to_lowercase();
+    format!(
+        "{} {} {}",
+        format.codec.ext(),
+        bit_depth,
+        format.sample_rate.as_u32(),
+    )
+}
+
+fn props_desc(props: Props) -> String {
+    format!("{} {}ch", format_desc(props.format), props.channels)
+}
+
+/// Reports the progress of `convert` to the user,
+/// either for people or as newline-delimited JSON events.
+pub enum Reporter {
+    Text(ProgressDisplay),
+    Json,
+}
+
+/// Counts of outputs by outcome.
+#[derive(Default)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub cancelled: usize,
+    /// Failed outputs, as input and output paths, grouped by error message.
+    pub failures: BTreeMap<String, Vec<(PathBuf, PathBuf)>>,
+    /// Whether the run as a whole was cancelled.
+    pub interrupted: bool,
+}
+
+/// Exit codes of the `convert` command.
+///
+/// 1 is left for errors that stop the command outright,
+/// and 2 for command line errors.
+pub mod exit_code {
+    pub const PARTIAL_FAILURE: u8 = 3;
+    pub const TOTAL_FAILURE: u8 = 4;
+    pub const CANCELLED: u8 = 130;
+}
+
+impl Reporter {
+    pub fn new(plan: &Plan, json: bool) -> AnyResult<Reporter> {
+        if json {
+            print_json_event(&json!({
+                "event": "plan",
+                "plan": plan,
+            }))?;
+            Ok(Reporter::Json)
+        } else {
+            Ok(Reporter::Text(ProgressDisplay::new(plan)))
+        }
+    }
+
+    pub fn response(&mut self, resp: &Response) -> AnyResult<()> {
+        let progress = match self {
+            Reporter::Json => {
+                return print_json_event(resp);
+            }
+            Reporter::Text(progress) => progress,
+        };
+
+        match resp {
+            Response::FileStarted { in_path, total_frames } => {
+                progress.file_started(in_path, *total_frames);
+            }
+            Response::Progress { in_path, frames, .. } => {
+                progress.progress(in_path, *frames);
+            }
+            Response::FileFinished { in_path } => {
+                progress.file_finished(in_path);
+            }
+            Response::NextResult(res) => {
+                progress.result(res);
+            }
+            Response::Done | Response::Cancelled => {
+                progress.finish();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&mut self, summary: &Summary) -> AnyResult<()> {
+        match self {
+            Reporter::Json => {
+                let failures: Vec<_> = summary.failures.iter().map(|(reason, paths)| {
+                    let outputs: Vec<_> = paths.iter().map(|(in_path, out_path)| {
+                        json!({
+                            "in_path": in_path,
+                            "out_path": out_path,
+                        })
+                    }).collect();
+                    json!({
+                        "reason": reason,
+                        "outputs": outputs,
+                    })
+                }).collect();
+                print_json_event(&json!({
+                    "event": "summary",
+                    "succeeded": summary.succeeded,
+                    "failed": summary.failed,
+                    "skipped": summary.skipped,
+                    "cancelled": summary.cancelled,
+                    "failures": failures,
+                    "exit_code": summary.exit_code(),
+                }))
+            }
+            Reporter::Text(_) => {
+                println!();
+                println!(
+                    "{} succeeded, {} failed, {} skipped, {} cancelled",
+                    summary.succeeded,
+                    summary.failed,
+                    summary.skipped,
+                    summary.cancelled,
+                );
+                for (reason, paths) in &summary.failures {
+                    println!();
+                    println!("failed ({}): {reason}", paths.len());
+                    for (in_path, out_path) in paths {
+                        println!("  {} -> {}", in_path.display(), out_path.display());
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Summary {
+    pub fn add(&mut self, res: &ConvertResult) {
+        match (&res.error, res.action) {
+            (Err(_), _) if res.is_cancelled() => self.cancelled += 1,
+            (Err(e), _) => {
+                self.failed += 1;
+                self.failures.entry(format!("{e:#}"))
+                    .or_default()
+                    .push((res.in_path.clone(), res.out_path.clone()));
+            }
+            (Ok(()), OutFileAction::Skip(_)) => self.skipped += 1,
+            (Ok(()), _) => self.succeeded += 1,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        if self.interrupted {
+            exit_code::CANCELLED
+        } else if self.failed > 0 && self.succeeded + self.skipped == 0 {
+            exit_code::TOTAL_FAILURE
+        } else if self.failed > 0 {
+            exit_code::PARTIAL_FAILURE
+        } else {
+            0
+        }
+    }
+}
+
+fn print_json_event(event: &impl Serialize) -> AnyResult<()> {
+    let json = serde_json::to_string(event)?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Renders conversion progress.
+///
+/// On a terminal this is a bar for overall progress
+/// plus a bar for each file being converted.
+/// Otherwise it is plain lines as files start and finish.
+pub struct ProgressDisplay {
+    bars: Option<Bars>,
+}
+
+struct Bars {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    files: HashMap<PathBuf, ProgressBar>,
+}
+
+impl ProgressDisplay {
+    pub fn new(plan: &Plan) -> ProgressDisplay {
+        if !io::stderr().is_terminal() {
+            return ProgressDisplay { bars: None };
+        }
+
+        let total_outputs = plan.outputs.iter()
+            .map(|infile_plan| infile_plan.outfiles.len())
+            .sum::<usize>();
+
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(total_outputs as u64));
+        overall.set_style(style("{elapsed_precise} [{bar:40}] {pos}/{len} outputs, ETA {eta}"));
+        overall.enable_steady_tick(Duration::from_millis(100));
+
+        ProgressDisplay {
+            bars: Some(Bars {
+                multi,
+                overall,
+                files: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn file_started(&mut self, in_path: &Path, total_frames: Option<u64>) {
+        let Some(bars) = &mut self.bars else {
+            println!("converting {}", in_path.display());
+            return;
+        };
+
+        let bar = match total_frames {
+            Some(total_frames) => {
+                let bar = ProgressBar::new(total_frames);
+                bar.set_style(style("  [{bar:30}] {percent:>3}% {per_sec:>14} ETA {eta:>4} {wide_msg}"));
+                bar
+            }
+            None => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(style("  {spinner} {pos} frames {per_sec:>14} {wide_msg}"));
+                bar
+            }
+        };
+        bar.set_message(file_name(in_path));
+        let bar = bars.multi.insert_before(&bars.overall, bar);
+        bars.files.insert(in_path.to_owned(), bar);
+    }
+
+    pub fn progress(&mut self, in_path: &Path, frames: u64) {
+        let Some(bars) = &mut self.bars else {
+            return;
+        };
+
+        if let Some(bar) = bars.files.get(in_path) {
+            bar.set_position(frames);
+        }
+    }
+
+    pub fn file_finished(&mut self, in_path: &Path) {
+        let Some(bars) = &mut self.bars else {
+            return;
+        };
+
+        if let Some(bar) = bars.files.remove(in_path) {
+            bar.finish_and_clear();
+            bars.multi.remove(&bar);
+        }
+    }
+
+    pub fn result(&mut self, res: &ConvertResult) {
+        let line = result_line(res);
+        match &mut self.bars {
+            Some(bars) => {
+                bars.overall.inc(1);
+                bars.multi.suspend(|| println!("{line}"));
+            }
+            None => {
+                println!("{line}");
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(bars) = self.bars.take() {
+            for bar in bars.files.values() {
+                bar.finish_and_clear();
+            }
+            bars.overall.finish_and_clear();
+        }
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("template")
+        .progress_chars("=> ")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn result_line(res: &ConvertResult) -> String {
+    let paths = format!("{} -> {}", res.in_path.display(), res.out_path.display());
+    match (&res.error, res.action) {
+        (Err(e), _) => format!("failed    {paths}: {e:#}"),
+        (Ok(()), OutFileAction::Skip(_)) => {
+            format!("skipped   {paths} ({})", action_desc(res.action))
+        }
+        (Ok(()), _) => format!("converted {paths}"),
+    }
+}
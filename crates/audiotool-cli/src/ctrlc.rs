@@ -1,20 +1,16 @@
-use std::sync::{Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-static LOCK: Mutex<bool> = Mutex::new(true);
-static CONDVAR: Condvar = Condvar::new();
+static PRESSED: AtomicBool = AtomicBool::new(false);
 
 pub fn init() {
     rmx::ctrlc::set_handler(ctrlc_handler).expect("ctrlc");
 }
 
-pub fn wait() {
-    let pending = LOCK.lock().expect("lock");
-    let _guard = CONDVAR.wait_while(pending, |pending| *pending).expect("lock");
-}
-
 fn ctrlc_handler() {
-    let mut pending = LOCK.lock().expect("lock");
-    *pending = false;
-    CONDVAR.notify_one();
+    PRESSED.store(true, Ordering::Relaxed);
 }
 
+/// Whether Ctrl-C has been pressed.
+pub fn pressed() -> bool {
+    PRESSED.load(Ordering::Relaxed)
+}
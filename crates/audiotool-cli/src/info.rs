@@ -0,0 +1,122 @@
+//! The `info` command.
+
+use rmx::prelude::*;
+use rmx::serde_json::{self, json};
+use rmx::walkdir::WalkDir;
+use audiotool::codecs;
+use audiotool::info::{self, FileInfo};
+use std::path::PathBuf;
+
+/// The files to inspect.
+///
+/// Directories are searched for files in any supported format.
+pub fn collect_paths(paths: &[PathBuf]) -> AnyResult<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let container = codecs::detect_container(entry.path()).ok().flatten();
+            if container.and_then(|container| container.codec()).is_some() {
+                files.push(entry.into_path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Print info for each file, returning whether all succeeded.
+pub fn print_info(paths: &[PathBuf], json: bool) -> AnyResult<bool> {
+    let mut all_ok = true;
+    let mut json_infos = vec![];
+
+    for (i, path) in paths.iter().enumerate() {
+        let info = info::file_info(path);
+        all_ok &= info.is_ok();
+
+        if json {
+            json_infos.push(match info {
+                Ok(info) => serde_json::to_value(info)?,
+                Err(e) => json!({
+                    "path": path,
+                    "error": format!("{e:#}"),
+                }),
+            });
+        } else {
+            if i > 0 {
+                println!();
+            }
+            match info {
+                Ok(info) => print_text(&info),
+                Err(e) => {
+                    println!("{}", path.display());
+                    println!("  error: {e:#}");
+                }
+            }
+        }
+    }
+
+    if json {
+        let json = serde_json::to_string_pretty(&json_infos)?;
+        println!("{json}");
+    }
+
+    Ok(all_ok)
+}
+
+fn print_text(info: &FileInfo) {
+    let props = &info.props;
+    let unknown = || S("unknown");
+
+    println!("{}", info.path.display());
+    println!("  container:   {}", info.container);
+    println!("  codec:       {:?}", props.format.codec);
+    println!("  channels:    {}", props.channels);
+    println!("  bit depth:   {:?}", props.format.bit_depth);
+    println!("  sample rate: {} Hz", props.format.sample_rate.as_u32());
+    println!(
+        "  frames:      {}",
+        info.frames.map(|frames| frames.to_string()).unwrap_or_else(unknown),
+    );
+    println!(
+        "  duration:    {}",
+        info.duration_secs.map(|secs| format!("{secs:.3} s")).unwrap_or_else(unknown),
+    );
+    println!("  file size:   {} bytes", info.file_size);
+    println!(
+        "  bitrate:     {}",
+        info.bitrate.map(|bps| format!("{} kbps", bps / 1000)).unwrap_or_else(unknown),
+    );
+
+    if let Some(md5) = &info.md5 {
+        let verified = match info.md5_verified {
+            Some(true) => "verified",
+            Some(false) => "MISMATCH",
+            None => "not checked",
+        };
+        println!("  md5:         {md5} ({verified})");
+    }
+
+    if !info.tags.is_empty() {
+        println!("  tags:");
+        for tag in &info.tags {
+            println!("    {} = {}", tag.key, tag.value);
+        }
+    }
+
+    if !info.chunks.is_empty() {
+        println!("  chunks:");
+        for chunk in &info.chunks {
+            println!("    {:<16} offset {:<10} size {}", chunk.id, chunk.offset, chunk.size);
+        }
+    }
+}
@@ -1,21 +1,26 @@
 use rmx::prelude::*;
 use rmx::clap::{self, Parser as _};
-use std::path::PathBuf;
+use audiotool::types::{BitDepth, SampleRate};
+use audiotool::convert::config::OverwritePolicy;
+use std::path::{Path, PathBuf};
 use std::fs;
-use std::thread;
+use std::process::ExitCode;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, SyncSender};
+use std::time::Duration;
 
 mod convert;
+mod info;
 mod split;
 mod ctrlc;
+mod watch;
 
-fn main() -> AnyResult<()> {
+fn main() -> AnyResult<ExitCode> {
     rmx::extras::init();
     ctrlc::init();
 
     let cli = Cli::parse();
-    cli.run()?;
-
-    Ok(())
+    cli.run()
 }
 
 #[derive(clap::Parser)]
@@ -29,6 +34,8 @@ struct Cli {
 #[derive(clap::Subcommand)]
 enum Command {
     Convert(ConvertCommand),
+    Clean(CleanCommand),
+    Info(InfoCommand),
     Template(TemplateCommand),
 }
 
@@ -38,7 +45,63 @@ struct Args {
 
 #[derive(clap::Args)]
 struct ConvertCommand {
+    /// A config file, or with `-o` a single file to convert.
+    path: PathBuf,
+    /// Convert `path` to this file, in the format of its extension.
+    #[arg(short = 'o', long = "out")]
+    out: Option<PathBuf>,
+    /// Output bit depth: f32, i24 or i16. Defaults to the input's.
+    #[arg(long, requires = "out")]
+    bit_depth: Option<BitDepth>,
+    /// Output sample rate, like 48k. Defaults to the input's.
+    #[arg(long, requires = "out")]
+    rate: Option<SampleRate>,
+    /// What to do about existing outputs:
+    /// overwrite, skip, error or keep-both.
+    /// Overrides the config's `overwrite`.
+    #[arg(long)]
+    overwrite: Option<OverwritePolicy>,
+    /// How many files to convert at once, and threads to convert them on.
+    /// Overrides the config's `jobs`; defaults to the number of CPUs.
+    #[arg(short = 'j', long)]
+    jobs: Option<NonZeroUsize>,
+    /// Print the plan without converting anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// After converting, keep watching the input directories,
+    /// converting files as they are added or changed, until Ctrl-C.
+    #[arg(long, conflicts_with_all = ["out", "dry_run"])]
+    watch: bool,
+    /// Print human-readable text, or newline-delimited JSON events.
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
+}
+
+#[derive(clap::ValueEnum)]
+#[derive(Copy, Clone, Default)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Remove outputs that no longer have a source.
+#[derive(clap::Args)]
+struct CleanCommand {
     config: PathBuf,
+    /// List stale outputs without deleting them.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Show the format and metadata of audio files.
+#[derive(clap::Args)]
+struct InfoCommand {
+    /// Files, or directories to search for audio files.
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    #[arg(long)]
+    json: bool,
 }
 
 #[derive(clap::Args)]
@@ -47,66 +110,106 @@ struct TemplateCommand {
 }
 
 impl Cli {
-    fn run(&self) -> AnyResult<()> {
+    fn run(&self) -> AnyResult<ExitCode> {
         match &self.cmd {
             Command::Convert(cmd) => cmd.run(&self.args),
-            Command::Template(cmd) => cmd.run(&self.args),
+            Command::Clean(cmd) => cmd.run(&self.args).map(|()| ExitCode::SUCCESS),
+            Command::Info(cmd) => cmd.run(&self.args),
+            Command::Template(cmd) => cmd.run(&self.args).map(|()| ExitCode::SUCCESS),
         }
     }
 }
 
 impl ConvertCommand {
-    fn run(&self, _args: &Args) -> AnyResult<()> {
+    fn run(&self, _args: &Args) -> AnyResult<ExitCode> {
         use audiotool::convert as cvt;
 
-        let config = fs::read_to_string(&self.config)?;
-        let config: cvt::config::Config = rmx::toml::from_str(&config)?;
-
-        let (tx, rx) = cvt::plan::spawn(config);
+        let json = matches!(self.output, OutputFormat::Json);
 
-        thread::spawn(move || {
-            ctrlc::wait();
-            let _ = tx.send(cvt::plan::Request::Cancel);
-        });
-
-        let plan = match rx.recv().expect("recv") {
-            cvt::plan::Response::Done(Ok(Some(plan))) => plan,
-            cvt::plan::Response::Done(Ok(None)) => {
-                // cancelled
-                return Ok(());
+        let (plan, config) = match &self.out {
+            Some(out) => {
+                let overwrite = self.overwrite.unwrap_or_default();
+                let mut plan = cvt::plan::single_file(&self.path, out, self.bit_depth, self.rate, overwrite)?;
+                plan.limits.jobs = self.jobs;
+                (plan, None)
             }
-            cvt::plan::Response::Done(Err(e)) => {
-                return Err(e);
+            None => {
+                let mut config = load_config(&self.path)?;
+                if let Some(overwrite) = self.overwrite {
+                    config.overwrite = overwrite;
+                }
+                if let Some(jobs) = self.jobs {
+                    config.limits.jobs = Some(jobs);
+                }
+                let Some(plan) = make_plan(config.clone())? else {
+                    return Ok(ExitCode::from(convert::exit_code::CANCELLED));
+                };
+                (plan, Some(config))
             }
         };
 
-        let (tx, rx) = cvt::exec::spawn(plan);
+        if self.dry_run {
+            convert::print_dry_run(&plan, json)?;
+            return Ok(ExitCode::SUCCESS);
+        }
 
-        thread::spawn(move || {
-            ctrlc::wait();
-            let _ = tx.send(cvt::exec::Request::Cancel);
-        });
+        let summary = execute_plan(plan, json)?;
 
-        loop {
-            let resp = rx.recv()?;
+        match config {
+            Some(config) if self.watch && !summary.interrupted => {
+                watch::watch(&config, json)
+            }
+            _ => Ok(ExitCode::from(summary.exit_code())),
+        }
+    }
+}
 
-            match resp {
-                cvt::exec::Response::NextResult(res) => {
-                    println!("{res:#?}");
-                }
-                cvt::exec::Response::Done => {
-                    break;
-                }
-                cvt::exec::Response::Cancelled => {
-                    break;
-                }
+impl CleanCommand {
+    fn run(&self, _args: &Args) -> AnyResult<()> {
+        use audiotool::convert as cvt;
+
+        let mut config = load_config(&self.config)?;
+        // Only the set of outputs matters, not whether they are up to date.
+        config.incremental = cvt::config::Incremental::Off;
+        // Plan the paths the template gives, without suffixes or errors.
+        config.overwrite = OverwritePolicy::Overwrite;
+
+        let Some(plan) = make_plan(config.clone())? else {
+            // cancelled
+            return Ok(());
+        };
+
+        let stale = cvt::clean::stale_outputs(&config, &plan)?;
+
+        for path in &stale {
+            if self.dry_run {
+                println!("would remove {}", path.display());
+            } else {
+                println!("removing {}", path.display());
             }
         }
 
+        if !self.dry_run {
+            cvt::clean::remove_outputs(&config, &stale)?;
+        }
+
         Ok(())
     }
 }
 
+impl InfoCommand {
+    fn run(&self, _args: &Args) -> AnyResult<ExitCode> {
+        let paths = info::collect_paths(&self.paths)?;
+        let all_ok = info::print_info(&paths, self.json)?;
+
+        if all_ok {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
 impl TemplateCommand {
     fn run(&self, _args: &Args) -> AnyResult<()> {
         use audiotool::convert as cvt;
@@ -126,3 +229,90 @@ impl TemplateCommand {
         Ok(())
     }
 }
+
+fn load_config(path: &Path) -> AnyResult<audiotool::convert::config::Config> {
+    let config = fs::read_to_string(path)?;
+    let config = rmx::toml::from_str(&config)?;
+    Ok(config)
+}
+
+/// Convert everything in the plan, reporting progress and a summary.
+fn execute_plan(
+    plan: audiotool::convert::plan::Plan,
+    json: bool,
+) -> AnyResult<convert::Summary> {
+    use audiotool::convert as cvt;
+
+    let mut reporter = convert::Reporter::new(&plan, json)?;
+    let mut summary = convert::Summary::default();
+
+    let (tx, rx) = cvt::exec::spawn(plan);
+    let mut cancel = Some(cvt::exec::Request::Cancel);
+
+    loop {
+        let resp = recv_or_cancel(&rx, &tx, &mut cancel)?;
+
+        reporter.response(&resp)?;
+
+        match resp {
+            cvt::exec::Response::FileStarted { .. }
+            | cvt::exec::Response::Progress { .. }
+            | cvt::exec::Response::FileFinished { .. } => { }
+            cvt::exec::Response::NextResult(res) => {
+                summary.add(&res);
+            }
+            cvt::exec::Response::Done => {
+                break;
+            }
+            cvt::exec::Response::Cancelled => {
+                summary.interrupted = true;
+                break;
+            }
+        }
+    }
+
+    reporter.summary(&summary)?;
+
+    Ok(summary)
+}
+
+/// Run the planner, returning `None` if cancelled.
+fn make_plan(
+    config: audiotool::convert::config::Config,
+) -> AnyResult<Option<audiotool::convert::plan::Plan>> {
+    use audiotool::convert as cvt;
+
+    let (tx, rx) = cvt::plan::spawn(config);
+    let mut cancel = Some(cvt::plan::Request::Cancel);
+
+    match recv_or_cancel(&rx, &tx, &mut cancel).expect("recv") {
+        cvt::plan::Response::Done(res) => res,
+    }
+}
+
+/// How often to check for Ctrl-C while waiting on a worker.
+const CTRLC_POLL: Duration = Duration::from_millis(100);
+
+/// Receive from a worker, sending it `cancel` once Ctrl-C is pressed.
+///
+/// This polls, rather than waiting for Ctrl-C on another thread,
+/// which would outlive the worker.
+fn recv_or_cancel<Req, Resp>(
+    rx: &Receiver<Resp>,
+    tx: &SyncSender<Req>,
+    cancel: &mut Option<Req>,
+) -> Result<Resp, RecvError> {
+    loop {
+        if ctrlc::pressed()
+            && let Some(cancel) = cancel.take()
+        {
+            let _ = tx.send(cancel);
+        }
+
+        match rx.recv_timeout(CTRLC_POLL) {
+            Ok(resp) => return Ok(resp),
+            Err(RecvTimeoutError::Timeout) => { }
+            Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Converting input files as they are added or changed.
+
+use rmx::prelude::*;
+use rmx::notify::{self, Watcher as _, RecursiveMode, Event, EventKind};
+use rmx::walkdir::WalkDir;
+use audiotool::convert as cvt;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::fs;
+
+use crate::{ctrlc, convert, execute_plan};
+
+/// How often to check on pending files and Ctrl-C.
+const TICK: Duration = Duration::from_millis(250);
+
+/// How long a file's size must stay the same before it's converted,
+/// so files still being written aren't.
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// Watch the input roots, converting changed files, until Ctrl-C.
+pub fn watch(config: &cvt::config::Config, json: bool) -> AnyResult<ExitCode> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    // Watched canonically, as `plan::for_inputs` matches events to roots.
+    for root in config.input_roots() {
+        let dir = fs::canonicalize(&root.dir)
+            .with_context(|| format!("watching {}", root.dir.display()))?;
+        watcher.watch(&dir, RecursiveMode::Recursive)
+            .with_context(|| format!("watching {}", root.dir.display()))?;
+    }
+
+    if !json {
+        eprintln!("watching for changes, Ctrl-C to stop");
+    }
+
+    let mut pending = Pending::default();
+    let mut any_failed = false;
+
+    while !ctrlc::pressed() {
+        match rx.recv_timeout(TICK) {
+            Ok(event) => {
+                pending.add(event);
+                for event in rx.try_iter() {
+                    pending.add(event);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => { }
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("file watcher stopped");
+            }
+        }
+
+        let settled = pending.settled();
+        if settled.is_empty() {
+            continue;
+        }
+
+        let plan = match cvt::plan::for_inputs(config, &settled) {
+            Ok(plan) => plan,
+            Err(e) => {
+                // Keep watching; the files may be fixed.
+                error!("{e:#}");
+                any_failed = true;
+                continue;
+            }
+        };
+
+        if plan.outputs.is_empty() {
+            continue;
+        }
+
+        let summary = execute_plan(plan, json)?;
+        if summary.interrupted {
+            return Ok(ExitCode::from(summary.exit_code()));
+        }
+        any_failed |= summary.failed > 0;
+    }
+
+    if any_failed {
+        Ok(ExitCode::from(convert::exit_code::PARTIAL_FAILURE))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Files that changed, with their size when last checked,
+/// and when that size was first seen.
+#[derive(Default)]
+struct Pending {
+    files: BTreeMap<PathBuf, (Option<u64>, Instant)>,
+}
+
+impl Pending {
+    fn add(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("watch error: {e}");
+                return;
+            }
+        };
+
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) => { }
+            _ => return,
+        }
+
+        for path in event.paths {
+            if path.is_dir() {
+                // A directory moved in may already be full of files.
+                let files = WalkDir::new(&path)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.file_type().is_file());
+                for entry in files {
+                    self.changed(entry.into_path());
+                }
+            } else {
+                self.changed(path);
+            }
+        }
+    }
+
+    fn changed(&mut self, path: PathBuf) {
+        self.files.insert(path, (None, Instant::now()));
+    }
+
+    /// Take the files whose size hasn't changed for `SETTLE`.
+    ///
+    /// Files that have gone away are dropped.
+    fn settled(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        let mut settled = vec![];
+
+        self.files.retain(|path, (size, since)| {
+            let Ok(metadata) = fs::metadata(path) else {
+                return false;
+            };
+            let new_size = Some(metadata.len());
+            if new_size != *size {
+                *size = new_size;
+                *since = now;
+                true
+            } else if now.duration_since(*since) >= SETTLE {
+                settled.push(path.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        settled
+    }
+}
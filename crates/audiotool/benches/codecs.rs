@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use audiotool::types::*;
+use audiotool::io::{Props, Buf};
+use audiotool::codecs::{self, EncoderSettings};
+use audiotool::testsupport::write_test_file;
+use std::hint::black_box;
+use std::path::Path;
+
+/// Ten seconds at 48 kHz.
+const FRAMES: u32 = 480_000;
+
+/// Every format each codec can both read and write.
+///
+/// Vorbis has neither a decoder nor an encoder yet.
+fn cases() -> Vec<(&'static str, Props)> {
+    let props = |codec, bit_depth| Props {
+        channels: 2,
+        format: Format {
+            codec,
+            bit_depth,
+            sample_rate: SampleRate::K48,
+        },
+    };
+
+    vec![
+        ("wav_f32", props(Codec::Wav, BitDepth::F32)),
+        ("wav_i24", props(Codec::Wav, BitDepth::I24)),
+        ("wav_i16", props(Codec::Wav, BitDepth::I16)),
+        ("flac_i24", props(Codec::Flac, BitDepth::I24)),
+        ("flac_i16", props(Codec::Flac, BitDepth::I16)),
+    ]
+}
+
+fn read_blocks(path: &Path) -> Vec<Buf> {
+    let mut reader = codecs::reader(path).expect("reader");
+    let mut blocks = vec![];
+    loop {
+        let mut buf = Buf::Uninit;
+        reader.read(&mut buf).expect("read");
+        if buf.is_empty() {
+            break;
+        }
+        blocks.push(buf);
+    }
+    blocks
+}
+
+fn read(c: &mut Criterion) {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool-bench").expect("tempdir");
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+
+    for (name, props) in cases() {
+        let path = tempdir.path().join(format!("{name}.{}", props.format.codec.ext()));
+        write_test_file(&path, props, FRAMES).expect("write");
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut reader = codecs::reader(&path).expect("reader");
+                let mut buf = Buf::Uninit;
+                loop {
+                    reader.read(&mut buf).expect("read");
+                    if buf.is_empty() {
+                        break;
+                    }
+                    black_box(&buf);
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn write(c: &mut Criterion) {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool-bench").expect("tempdir");
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+
+    for (name, props) in cases() {
+        let ext = props.format.codec.ext();
+        let in_path = tempdir.path().join(format!("{name}.{ext}"));
+        write_test_file(&in_path, props, FRAMES).expect("write");
+        let blocks = read_blocks(&in_path);
+        let out_path = tempdir.path().join(format!("{name}-out.{ext}"));
+        let settings = EncoderSettings::default_for(props.format.codec);
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut writer = codecs::writer(&out_path, props, &settings).expect("writer");
+                for block in &blocks {
+                    writer.write(block).expect("write");
+                }
+                writer.finalize().expect("finalize");
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, read, write);
+criterion_main!(benches);
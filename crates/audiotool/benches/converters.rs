@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use audiotool::types::*;
+use audiotool::io::{Buf, DEFAULT_BLOCK_FRAMES};
+use audiotool::bitdepth::BitDepthConverter;
+use audiotool::samplerate::SampleRateConverter;
+use std::hint::black_box;
+
+const CHANNELS: u16 = 2;
+
+/// One second at 48 kHz, in blocks as a reader returns them.
+const BLOCKS: usize = 12;
+
+fn f32_blocks() -> Vec<Buf> {
+    let samples = DEFAULT_BLOCK_FRAMES * CHANNELS as usize;
+    (0..BLOCKS).map(|block| {
+        Buf::F32(
+            (0..samples)
+                .map(|i| ((block * samples + i) as f32 * 0.01).sin() * 0.5)
+                .collect()
+        )
+    }).collect()
+}
+
+fn frames() -> u64 {
+    (BLOCKS * DEFAULT_BLOCK_FRAMES) as u64
+}
+
+fn bit_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bit_depth");
+    group.throughput(Throughput::Elements(frames()));
+
+    let f32_blocks = f32_blocks();
+    let cases = [
+        ("f32_to_i24", BitDepth::I24, BitDepth::I24),
+        ("f32_to_i24_dither", BitDepth::I24, BitDepth::F32),
+        ("f32_to_i16_dither", BitDepth::I16, BitDepth::I24),
+    ];
+    for (name, outbits, origbits) in cases {
+        let mut converter = BitDepthConverter::new(BitDepth::F32, outbits, origbits);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for block in &f32_blocks {
+                    black_box(converter.convert(block));
+                }
+            })
+        });
+    }
+
+    // And back, as every input is converted to F32 first.
+    let mut to_i24 = BitDepthConverter::new(BitDepth::F32, BitDepth::I24, BitDepth::I24);
+    let i24_blocks: Vec<Buf> = f32_blocks.iter().map(|block| {
+        match to_i24.convert(block) {
+            Buf::I24(buf) => Buf::I24(buf.clone()),
+            _ => unreachable!(),
+        }
+    }).collect();
+    let mut converter = BitDepthConverter::new(BitDepth::I24, BitDepth::F32, BitDepth::I24);
+    group.bench_function("i24_to_f32", |b| {
+        b.iter(|| {
+            for block in &i24_blocks {
+                black_box(converter.convert(block));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn sample_rate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample_rate");
+    group.throughput(Throughput::Elements(frames()));
+    group.sample_size(20);
+
+    let blocks = f32_blocks();
+    let cases = [
+        ("48k_to_192k", SampleRate::K48, SampleRate::K192),
+        ("192k_to_48k", SampleRate::K192, SampleRate::K48),
+    ];
+    for (name, inrate, outrate) in cases {
+        let mut converter = SampleRateConverter::new(inrate, outrate, CHANNELS);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for block in &blocks {
+                    black_box(converter.convert(block));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bit_depth, sample_rate);
+criterion_main!(benches);
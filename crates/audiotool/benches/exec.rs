@@ -0,0 +1,59 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rmx::prelude::*;
+use audiotool::types::*;
+use audiotool::io::Props;
+use audiotool::convert::config::Incremental;
+use audiotool::testsupport::{write_test_file, test_config, run_convert};
+
+const FILES: u32 = 4;
+
+/// Ten seconds at 48 kHz.
+const FRAMES: u32 = 480_000;
+
+/// Converting a directory of 24-bit WAV to 16-bit FLAC
+/// and 192 kHz 24-bit FLAC, the usual release formats.
+fn exec(c: &mut Criterion) {
+    let inprops = Props {
+        channels: 2,
+        format: Format {
+            codec: Codec::Wav,
+            bit_depth: BitDepth::I24,
+            sample_rate: SampleRate::K48,
+        },
+    };
+
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool-bench").expect("tempdir");
+    let mut config = test_config(tempdir.path(), inprops, Format {
+        codec: Codec::Flac,
+        bit_depth: BitDepth::I16,
+        sample_rate: SampleRate::K48,
+    });
+    config.formats.push(Format {
+        codec: Codec::Flac,
+        bit_depth: BitDepth::I24,
+        sample_rate: SampleRate::K192,
+    }.into());
+    config.out_path_template = S("{{out_root_dir}}/{{sample_rate}}-{{bit_depth}}/{{file_stem}}.{{format_ext}}");
+    config.incremental = Incremental::Off;
+    config.verify = false;
+
+    std::fs::create_dir_all(&config.reference_tracks_dir).expect("mkdir");
+    for i in 0..FILES {
+        let path = config.reference_tracks_dir.join(format!("{i}.wav"));
+        write_test_file(&path, inprops, FRAMES).expect("write");
+    }
+
+    let mut group = c.benchmark_group("exec");
+    group.throughput(Throughput::Elements((FILES * FRAMES) as u64));
+    group.sample_size(10);
+    group.bench_function("wav_to_flac", |b| {
+        b.iter(|| {
+            let results = run_convert(config.clone()).expect("convert");
+            assert!(results.iter().all(|result| result.error.is_ok()));
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, exec);
+criterion_main!(benches);
@@ -4,6 +4,11 @@ mod vorbis;
 mod opus { }
 mod aac { }
 mod alac { }
+mod detect;
+mod settings;
+
+pub use detect::{Container, OggCodec, detect_container};
+pub use settings::{EncoderSettings, FlacSettings, VorbisSettings};
 
 use rmx::prelude::*;
 use std::path::Path;
@@ -11,20 +16,31 @@ use crate::io::*;
 use crate::types::*;
 use std::ffi::OsStr;
 
+/// Open a reader for any supported file.
+///
+/// The codec is identified by content,
+/// falling back to the file extension if the content isn't recognized.
 pub fn reader(path: &Path) -> AnyResult<Box<dyn PcmReader>> {
+    if let Some(container) = detect_container(path)? {
+        return match container.codec() {
+            Some(codec) => Ok(codec_reader(path, codec)),
+            None => Err(anyhow!("unsupported format: {container}")),
+        };
+    }
+
     let ext = path.extension()
         .map(OsStr::to_string_lossy)
         .as_deref()
         .map(str::to_string);
     match ext.as_deref() {
         Some("wav") => {
-            Ok(Box::new(wav::WavPcmReader::new(path)))
+            Ok(codec_reader(path, Codec::Wav))
         }
         Some("flac") => {
-            Ok(Box::new(flac::FlacPcmReader::new(path)))
+            Ok(codec_reader(path, Codec::Flac))
         }
         Some("ogg") => {
-            Ok(Box::new(vorbis::VorbisPcmReader::new(path)))
+            Ok(codec_reader(path, Codec::Vorbis))
         }
         Some(ext) => {
             Err(anyhow!("unknown extension: `{ext}`"))
@@ -35,19 +51,70 @@ pub fn reader(path: &Path) -> AnyResult<Box<dyn PcmReader>> {
     }
 }
 
-pub fn writer(
+/// Read the properties of any supported file.
+pub fn probe(path: &Path) -> AnyResult<Props> {
+    let mut reader = reader(path)?;
+    reader.props()
+}
+
+/// Decode a whole FLAC file and check it against its MD5 signature.
+///
+/// Files without a signature always pass.
+pub fn verify_flac_md5(path: &Path) -> AnyResult<bool> {
+    let mut reader = flac::FlacPcmReader::new(path);
+    let mut buf = Buf::Uninit;
+    loop {
+        reader.read(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+    }
+    reader.finish()
+}
+
+pub fn codec_reader(
     path: &Path,
-    props: Props,
-) -> Box<dyn PcmWriter> {
-    match props.format.codec {
+    codec: Codec,
+) -> Box<dyn PcmReader> {
+    match codec {
         Codec::Wav => {
-            Box::new(wav::WavPcmWriter::new(path, props))
+            Box::new(wav::WavPcmReader::new(path))
         }
         Codec::Flac => {
-            Box::new(flac::FlacPcmWriter::new(path, props))
+            Box::new(flac::FlacPcmReader::new(path))
         }
         Codec::Vorbis => {
-            Box::new(vorbis::VorbisPcmWriter::new(path, props))
+            Box::new(vorbis::VorbisPcmReader::new(path))
+        }
+    }
+}
+
+pub fn writer(
+    path: &Path,
+    props: Props,
+    settings: &EncoderSettings,
+) -> AnyResult<Box<dyn PcmWriter>> {
+    if !props.is_usable() {
+        bail!(
+            "unsupported output: {} channels of {:?} {:?}",
+            props.channels,
+            props.format.codec,
+            props.format.bit_depth,
+        );
+    }
+
+    match (props.format.codec, settings) {
+        (Codec::Wav, EncoderSettings::Wav) => {
+            Ok(Box::new(wav::WavPcmWriter::new(path, props)?))
+        }
+        (Codec::Flac, EncoderSettings::Flac(settings)) => {
+            Ok(Box::new(flac::FlacPcmWriter::new(path, props, settings)?))
+        }
+        (Codec::Vorbis, EncoderSettings::Vorbis(settings)) => {
+            Ok(Box::new(vorbis::VorbisPcmWriter::new(path, props, settings)?))
+        }
+        (codec, settings) => {
+            bail!("{codec:?} writer given settings for another codec: {settings:?}");
         }
     }
 }
@@ -0,0 +1,142 @@
+use rmx::prelude::*;
+use rmx::serde::{Serialize, Deserialize};
+use crate::types::Codec;
+use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::fmt;
+
+/// A container format recognized by its leading bytes.
+///
+/// Not all of these can be decoded;
+/// see [`Container::codec`].
+#[derive(Serialize, Deserialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum Container {
+    Wav,
+    Rf64,
+    Flac,
+    Ogg(OggCodec),
+    Aiff,
+    /// MPEG audio frames, possibly after an ID3v2 tag.
+    Mpeg,
+    /// ISO base media, e.g. MP4 or M4A.
+    Mp4,
+}
+
+/// The codec of the first logical stream in an Ogg file.
+#[derive(Serialize, Deserialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum OggCodec {
+    Vorbis,
+    Opus,
+    Flac,
+    Unknown,
+}
+
+impl Container {
+    /// The codec used to decode this container, if supported.
+    pub fn codec(&self) -> Option<Codec> {
+        match self {
+            Container::Wav => Some(Codec::Wav),
+            Container::Flac => Some(Codec::Flac),
+            Container::Ogg(OggCodec::Vorbis) => Some(Codec::Vorbis),
+            Container::Rf64
+            | Container::Ogg(_)
+            | Container::Aiff
+            | Container::Mpeg
+            | Container::Mp4 => None,
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Container::Wav => "WAV",
+            Container::Rf64 => "RF64",
+            Container::Flac => "FLAC",
+            Container::Ogg(OggCodec::Vorbis) => "Ogg Vorbis",
+            Container::Ogg(OggCodec::Opus) => "Ogg Opus",
+            Container::Ogg(OggCodec::Flac) => "Ogg FLAC",
+            Container::Ogg(OggCodec::Unknown) => "Ogg",
+            Container::Aiff => "AIFF",
+            Container::Mpeg => "MPEG audio",
+            Container::Mp4 => "MP4",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Identify a file's container from its content.
+///
+/// Returns `None` if the content isn't recognized.
+pub fn detect_container(path: &Path) -> AnyResult<Option<Container>> {
+    let mut file = File::open(path)?;
+    detect(&mut file)
+}
+
+// Enough for an Ogg page header with a full segment table
+// and the start of its first packet.
+const HEADER_LEN: usize = 512;
+
+fn detect(file: &mut (impl Read + Seek)) -> AnyResult<Option<Container>> {
+    let header = read_header(file)?;
+    let header = &header[..];
+
+    let container = match header {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Container::Wav,
+        [b'R', b'F', b'6', b'4', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Container::Rf64,
+        [b'B', b'W', b'6', b'4', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Container::Rf64,
+        [b'f', b'L', b'a', b'C', ..] => Container::Flac,
+        [b'O', b'g', b'g', b'S', ..] => Container::Ogg(ogg_codec(header)),
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'F', ..] => Container::Aiff,
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', b'C', ..] => Container::Aiff,
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Container::Mp4,
+        [b'I', b'D', b'3', _, _, _, s0, s1, s2, s3, ..] => {
+            // An ID3v2 tag, which may precede MPEG audio or FLAC.
+            // The size is syncsafe: 7 bits per byte.
+            let size = [*s0, *s1, *s2, *s3].iter()
+                .fold(0_u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+            file.seek(SeekFrom::Start(10 + size))?;
+            let after = read_header(file)?;
+            match &after[..] {
+                [b'f', b'L', b'a', b'C', ..] => Container::Flac,
+                _ => Container::Mpeg,
+            }
+        }
+        [0xff, b1, ..] if b1 & 0xe0 == 0xe0 => Container::Mpeg,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(container))
+}
+
+fn read_header(file: &mut impl Read) -> AnyResult<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Identify the codec from the first packet of the first Ogg page.
+fn ogg_codec(page: &[u8]) -> OggCodec {
+    // The page header is 27 bytes, then the segment table.
+    let Some(segments) = page.get(26) else {
+        return OggCodec::Unknown;
+    };
+    let packet = page.get(27 + *segments as usize..).unwrap_or_default();
+
+    if packet.starts_with(b"\x01vorbis") {
+        OggCodec::Vorbis
+    } else if packet.starts_with(b"OpusHead") {
+        OggCodec::Opus
+    } else if packet.starts_with(b"\x7fFLAC") {
+        OggCodec::Flac
+    } else {
+        OggCodec::Unknown
+    }
+}
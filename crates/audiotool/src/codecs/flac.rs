@@ -1,6 +1,7 @@
 use rmx::prelude::*;
 use crate::types::{Format, BitDepth, SampleRate, Codec};
-use crate::io::{PcmReader, PcmWriter, Buf, Props};
+use crate::io::{PcmReader, PcmWriter, Buf, Props, DEFAULT_BLOCK_FRAMES};
+use crate::codecs::FlacSettings;
 use std::path::Path;
 use std::io::{BufReader, BufWriter};
 use std::fs::File;
@@ -13,10 +14,15 @@ use rmx::libc::c_char;
 pub struct FlacPcmReader {
     decoder: AnyResult<NonNull<FLAC__StreamDecoder>>,
     cbdata: *mut ReaderCallbackData,
+    block_frames: usize,
 }
 
 struct ReaderCallbackData {
     props: Option<Props>,
+    // From STREAMINFO, where zero means unknown.
+    total_samples: u64,
+    // Decoded samples not yet read,
+    // as FLAC frames don't line up with our blocks.
     buf: Buf,
     error: AnyResult<()>,
 }
@@ -27,23 +33,20 @@ impl FlacPcmReader {
     pub fn new(path: &Path) -> FlacPcmReader {
         let mut cbdata = Box::new(ReaderCallbackData {
             props: None,
+            total_samples: 0,
             buf: Buf::Uninit,
             error: Ok(()),
         });
 
         unsafe {
-            let decoder = FLAC__stream_decoder_new();
-            let decoder = NonNull::new(decoder);
-            let decoder = decoder.ok_or_else(|| {
-                anyhow!("unable to allocate FLAC decoder")
-            });
+            let decoder = path_to_cstring(path).and_then(|path| {
+                let decoder = FLAC__stream_decoder_new();
+                let decoder = NonNull::new(decoder).ok_or_else(|| {
+                    anyhow!("unable to allocate FLAC decoder")
+                })?;
 
-            let decoder = if let Ok(decoder) = decoder {
                 FLAC__stream_decoder_set_md5_checking(decoder.as_ptr(), true as FLAC__bool);
 
-                let path = path.to_str().expect("todo utf8 path").to_owned();
-                let path = CString::new(path).expect("path with nul bytes").to_owned();
-
                 let status = FLAC__stream_decoder_init_file(
                     decoder.as_ptr(),
                     path.as_ptr(),
@@ -60,18 +63,37 @@ impl FlacPcmReader {
                     let err_str = code_to_string(&FLAC__StreamDecoderInitStatusString, status);
                     Err(anyhow!("{err_str}"))
                 }
-            } else {
-                decoder
-            };
+            });
 
             FlacPcmReader {
                 decoder,
                 cbdata: Box::leak(cbdata) as *mut ReaderCallbackData,
+                block_frames: DEFAULT_BLOCK_FRAMES,
             }
         }
     }
 }
 
+impl FlacPcmReader {
+    /// Finish decoding, checking the decoded audio
+    /// against the MD5 signature in STREAMINFO.
+    ///
+    /// Returns `false` on a mismatch.
+    /// Only meaningful after reading to the end of the stream,
+    /// and the reader can't be read from afterwards.
+    pub fn finish(&mut self) -> AnyResult<bool> {
+        let decoder = self.decoder.as_ref()
+            .map_err(|e| anyhow!("{e}"))?;
+
+        unsafe {
+            Ok(FLAC__stream_decoder_finish(decoder.as_ptr()) != 0)
+        }
+    }
+}
+
+// Panicking in these callbacks would unwind into libFLAC and abort,
+// so errors are stored in the callback data for the reader to return.
+
 extern "C" fn decoder_write_callback(
     decoder: *const FLAC__StreamDecoder,
     frame: *const FLAC__Frame,
@@ -86,34 +108,49 @@ extern "C" fn decoder_write_callback(
     unsafe {
         let cbdata = &mut *(cbdata as *mut ReaderCallbackData);
 
-        // Can't support properties changing between frames.
-        if let Some(props) = cbdata.props {
-            assert_eq!(
-                props.format.sample_rate.as_u32(),
-                (*frame).header.sample_rate,
-            );
-            match props.format.bit_depth {
-                BitDepth::F32 => unreachable!(),
-                BitDepth::I24 => assert_eq!(
-                    24, (*frame).header.bits_per_sample,
-                ),
-                BitDepth::I16 => assert_eq!(
-                    16, (*frame).header.bits_per_sample,
-                ),
-            }
-            assert_eq!(
-                props.channels as u32,
-                (*frame).header.channels,
-            );
+        if cbdata.error.is_err() {
+            return FLAC__STREAM_DECODER_WRITE_STATUS_ABORT;
+        }
+
+        if let Err(e) = write_frame(cbdata, &*frame, buffer) {
+            cbdata.error = Err(e);
+            return FLAC__STREAM_DECODER_WRITE_STATUS_ABORT;
+        }
+    }
+
+    FLAC__STREAM_DECODER_WRITE_STATUS_CONTINUE
+}
+
+unsafe fn write_frame(
+    cbdata: &mut ReaderCallbackData,
+    frame: &FLAC__Frame,
+    buffer: *const *const i32,
+) -> AnyResult<()> {
+    let header = &frame.header;
+
+    // Can't support properties changing between frames.
+    if let Some(props) = cbdata.props {
+        let bits_per_sample = match props.format.bit_depth {
+            BitDepth::F32 => 32,
+            BitDepth::I24 => 24,
+            BitDepth::I16 => 16,
+        };
+        if header.sample_rate != props.format.sample_rate.as_u32()
+            || header.bits_per_sample != bits_per_sample
+            || header.channels != props.channels as u32
+        {
+            bail!("flac stream properties change between frames");
         }
+    }
 
-        match (*frame).header.bits_per_sample {
+    unsafe {
+        match header.bits_per_sample {
             24 => {
                 let buf = cbdata.buf.i24_mut();
 
                 // Interleave channels from individual buffers
-                for block in 0..(*frame).header.blocksize as isize {
-                    for ch in 0..(*frame).header.channels as isize {
+                for block in 0..header.blocksize as isize {
+                    for ch in 0..header.channels as isize {
                         let channel_buf = *buffer.offset(ch);
                         let sample = *channel_buf.offset(block);
                         buf.push(sample);
@@ -124,19 +161,19 @@ extern "C" fn decoder_write_callback(
                 let buf = cbdata.buf.i16_mut();
 
                 // Interleave channels from individual buffers
-                for block in 0..(*frame).header.blocksize as isize {
-                    for ch in 0..(*frame).header.channels as isize {
+                for block in 0..header.blocksize as isize {
+                    for ch in 0..header.channels as isize {
                         let channel_buf = *buffer.offset(ch);
                         let sample = *channel_buf.offset(block);
                         buf.push(sample as i16);
                     }
                 }
             }
-            v => todo!("flac bits per sample {v}"),
+            v => bail!("unsupported flac bits per sample: {v}"),
         }
     }
 
-    FLAC__STREAM_DECODER_WRITE_STATUS_CONTINUE
+    Ok(())
 }
 
 extern "C" fn decoder_metadata_callback(
@@ -154,40 +191,48 @@ extern "C" fn decoder_metadata_callback(
         if (*metadata).type_ == FLAC__METADATA_TYPE_STREAMINFO {
             let stream_info = &(*metadata).data.stream_info;
 
-            let bit_depth = match stream_info.bits_per_sample {
-                24 => BitDepth::I24,
-                16 => BitDepth::I16,
-                v => todo!("flac bits per sample {v}"),
-            };
-
-            let sample_rate = match stream_info.sample_rate {
-                192_000 => SampleRate::K192,
-                48_000 => SampleRate::K48,
-                v => todo!("flac sample rate {v}"),
-            };
-
-            let channels = match stream_info.channels {
-                1 => 1,
-                2 => 2,
-                v => todo!("flac channels {v}"),
-            };
-
-            let props = Props {
-                channels,
-                format: Format {
-                    codec: Codec::Flac,
-                    bit_depth,
-                    sample_rate,
+            match stream_info_props(stream_info) {
+                Ok(props) => {
+                    cbdata.props = Some(props);
+                    cbdata.total_samples = stream_info.total_samples;
                 }
-            };
-
-            assert!(cbdata.props.is_none());
-
-            cbdata.props = Some(props);
+                Err(e) => {
+                    cbdata.error = Err(e);
+                }
+            }
         }
     }
 }
 
+fn stream_info_props(stream_info: &FLAC__StreamMetadata_StreamInfo) -> AnyResult<Props> {
+    let bit_depth = match stream_info.bits_per_sample {
+        24 => BitDepth::I24,
+        16 => BitDepth::I16,
+        v => bail!("unsupported flac bits per sample: {v}"),
+    };
+
+    let sample_rate = match stream_info.sample_rate {
+        192_000 => SampleRate::K192,
+        48_000 => SampleRate::K48,
+        v => bail!("unsupported flac sample rate: {v} hz"),
+    };
+
+    let channels = match stream_info.channels {
+        1 => 1,
+        2 => 2,
+        v => bail!("unsupported flac channels: {v}"),
+    };
+
+    Ok(Props {
+        channels,
+        format: Format {
+            codec: Codec::Flac,
+            bit_depth,
+            sample_rate,
+        }
+    })
+}
+
 extern "C" fn decoder_error_callback(
     decoder: *const FLAC__StreamDecoder,
     status: FLAC__StreamDecoderErrorStatus,
@@ -250,15 +295,39 @@ impl PcmReader for FlacPcmReader {
                 }
             }
 
-            assert!((*self.cbdata).props.is_some());
+            // The metadata callback can't fail the decoder,
+            // so its errors are only seen here.
+            if let Err(e) = &(*self.cbdata).error {
+                bail!("{e}");
+            }
+
+            if (*self.cbdata).props.is_none() {
+                bail!("flac stream has no STREAMINFO");
+            }
+
             self.props()
         }
     }
 
+    fn set_block_frames(&mut self, frames: usize) {
+        assert!(frames > 0);
+        self.block_frames = frames;
+    }
+
+    fn total_frames(&mut self) -> AnyResult<Option<u64>> {
+        // Reads the metadata if it hasn't been yet.
+        self.props()?;
+
+        // nb: flac "samples" are frames.
+        let total_samples = unsafe { (*self.cbdata).total_samples };
+        Ok((total_samples != 0).then_some(total_samples))
+    }
+
     fn read(
         &mut self,
         buf: &mut Buf,
     ) -> AnyResult<()> {
+        let block_samples = self.block_frames * self.props()?.channels as usize;
         let decoder = self.decoder.as_ref()
             .map_err(|e| anyhow!("{e}"))?;
 
@@ -266,6 +335,9 @@ impl PcmReader for FlacPcmReader {
 
         unsafe {
             loop {
+                let state = FLAC__stream_decoder_get_state(decoder.as_ptr());
+                let end_of_stream = state == FLAC__STREAM_DECODER_END_OF_STREAM;
+
                 // Take and drop references to the shared cbdata
                 // before calling the decoder, which will mutate them.
                 {
@@ -276,19 +348,18 @@ impl PcmReader for FlacPcmReader {
                     }
 
                     let self_buf = &mut (*self.cbdata).buf;
+                    let have_block = !self_buf.is_empty() && self_buf.len() >= block_samples;
 
-                    if !self_buf.is_empty() {
-                        std::mem::swap(self_buf, buf);
-                        assert!(self_buf.is_empty());
+                    if have_block || (end_of_stream && !self_buf.is_empty()) {
+                        self_buf.drain_front_into(block_samples, buf);
                         return Ok(());
                     }
                 }
 
-                let state = FLAC__stream_decoder_get_state(decoder.as_ptr());
-                if state == FLAC__STREAM_DECODER_END_OF_STREAM {
+                if end_of_stream {
                     return Ok(());
                 }
-                
+
                 let ok = FLAC__stream_decoder_process_single(decoder.as_ptr());
 
                 if ok == 0 {
@@ -306,8 +377,12 @@ impl PcmReader for FlacPcmReader {
 }
 
 pub struct FlacPcmWriter {
-    encoder: AnyResult<NonNull<FLAC__StreamEncoder>>,
+    encoder: NonNull<FLAC__StreamEncoder>,
+    // Metadata blocks to write, which must outlive the encoder.
+    metadata: Vec<NonNull<FLAC__StreamMetadata>>,
     props: Props,
+    // I16 samples widened for the encoder, reused across writes.
+    scratch: Vec<i32>,
 }
 
 unsafe impl Send for FlacPcmWriter { }
@@ -316,27 +391,37 @@ impl FlacPcmWriter {
     pub fn new(
         path: &Path,
         props: Props,
-    ) -> FlacPcmWriter {
+        settings: &FlacSettings,
+    ) -> AnyResult<FlacPcmWriter> {
         assert_eq!(props.format.codec, Codec::Flac);
 
+        let bits_per_sample = match props.format.bit_depth {
+            BitDepth::F32 => bail!("FLAC does not support floating point samples"),
+            BitDepth::I24 => 24,
+            BitDepth::I16 => 16,
+        };
+
         unsafe {
             let encoder = FLAC__stream_encoder_new();
             let encoder = NonNull::new(encoder);
             let encoder = encoder.ok_or_else(|| {
                 anyhow!("unable to allocate FLAC encoder")
-            });
+            })?;
 
-            let bits_per_sample = match props.format.bit_depth {
-                BitDepth::F32 => unreachable!(),
-                BitDepth::I24 => 24,
-                BitDepth::I16 => 16,
+            // From here on the writer owns the encoder and metadata,
+            // and deletes them on error.
+            let mut writer = FlacPcmWriter {
+                encoder,
+                metadata: vec![],
+                props,
+                scratch: vec![],
             };
 
-            let encoder = if let Ok(encoder) = encoder {
-                let ok = {
-	                //FLAC__stream_encoder_set_verify(encoder.as_ptr(), true as FLAC__bool) != 0
-                    // fixme don't hardcode 5
-	                FLAC__stream_encoder_set_compression_level(encoder.as_ptr(), 5) != 0
+            {
+                let mut ok = {
+	                FLAC__stream_encoder_set_verify(encoder.as_ptr(), settings.verify as FLAC__bool) != 0
+	                    // Sets the blocksize, so must come before `set_blocksize`.
+	                    && FLAC__stream_encoder_set_compression_level(encoder.as_ptr(), settings.compression_level) != 0
 	                    && FLAC__stream_encoder_set_channels(encoder.as_ptr(), props.channels as u32) != 0
 	                    && FLAC__stream_encoder_set_bits_per_sample(encoder.as_ptr(), bits_per_sample) != 0
 	                    && FLAC__stream_encoder_set_sample_rate(encoder.as_ptr(), props.format.sample_rate.as_u32()) != 0
@@ -344,22 +429,37 @@ impl FlacPcmWriter {
                     //FLAC__stream_encoder_set_total_samples_estimate(encoder, total_samples);
                 };
 
-                if ok {
-                    Ok(encoder)
-                } else {
+                if let Some(blocksize) = settings.blocksize {
+                    ok = ok && FLAC__stream_encoder_set_blocksize(encoder.as_ptr(), blocksize) != 0;
+                }
+
+                if let Some(padding) = settings.padding {
+                    let block = FLAC__metadata_object_new(FLAC__METADATA_TYPE_PADDING);
+                    let block = NonNull::new(block).ok_or_else(|| {
+                        anyhow!("unable to allocate FLAC metadata")
+                    })?;
+                    (*block.as_ptr()).length = padding;
+                    writer.metadata.push(block);
+
+                    let mut blocks: Vec<_> = writer.metadata.iter()
+                        .map(|block| block.as_ptr())
+                        .collect();
+                    ok = ok && FLAC__stream_encoder_set_metadata(
+                        encoder.as_ptr(),
+                        blocks.as_mut_ptr(),
+                        blocks.len() as u32,
+                    ) != 0;
+                }
+
+                if !ok {
                     let state = FLAC__stream_encoder_get_state(encoder.as_ptr());
                     let err_str = code_to_string(&FLAC__StreamEncoderStateString, state);
-                    FLAC__stream_encoder_delete(encoder.as_ptr());
-                    Err(anyhow!("{err_str}"))
+                    bail!("{err_str}");
                 }
-            } else {
-                encoder
-            };
-
-            let encoder = if let Ok(encoder) = encoder {
+            }
 
-                let path = path.to_str().expect("todo utf8 path").to_owned();
-                let path = CString::new(path).expect("path with nul bytes").to_owned();
+            {
+                let path = path_to_cstring(path)?;
 
                 let status = FLAC__stream_encoder_init_file(
                     encoder.as_ptr(),
@@ -368,21 +468,13 @@ impl FlacPcmWriter {
                     std::ptr::null_mut(),
                 );
 
-                if status == FLAC__STREAM_ENCODER_INIT_STATUS_OK {
-                    Ok(encoder)
-                } else {
-                    FLAC__stream_encoder_delete(encoder.as_ptr());
+                if status != FLAC__STREAM_ENCODER_INIT_STATUS_OK {
                     let err_str = code_to_string(&FLAC__StreamEncoderInitStatusString, status);
-                    Err(anyhow!("{err_str}"))
+                    bail!("{err_str}");
                 }
-            } else {
-                encoder
-            };
-
-            FlacPcmWriter {
-                encoder,
-                props,
             }
+
+            Ok(writer)
         }
     }
 }
@@ -390,8 +482,9 @@ impl FlacPcmWriter {
 impl Drop for FlacPcmWriter {
     fn drop(&mut self) {
         unsafe {
-            if let Ok(encoder) = self.encoder.as_ref() {
-                FLAC__stream_encoder_delete(encoder.as_ptr());
+            FLAC__stream_encoder_delete(self.encoder.as_ptr());
+            for block in &self.metadata {
+                FLAC__metadata_object_delete(block.as_ptr());
             }
         }
     }
@@ -402,20 +495,19 @@ impl PcmWriter for FlacPcmWriter {
         &mut self,
         buf: &Buf,
     ) -> AnyResult<()> {
-        let encoder = self.encoder.as_ref()
-            .map_err(|e| anyhow!("{e}"))?;
+        let encoder = self.encoder;
 
         assert_eq!(buf.bit_depth(), Some(self.props.format.bit_depth));
 
         unsafe {
-            let mut tmp_buf = Vec::<i32>::new();
             let samples = match buf {
                 Buf::Uninit => unreachable!(),
                 Buf::F32(_) => unreachable!(),
                 Buf::I24(buf) => &buf,
                 Buf::I16(buf) => {
-                    tmp_buf = buf.iter().map(|s| *s as i32).collect();
-                    &tmp_buf
+                    self.scratch.truncate(0);
+                    self.scratch.extend(buf.iter().map(|s| *s as i32));
+                    &self.scratch
                 }
             };
 
@@ -439,8 +531,7 @@ impl PcmWriter for FlacPcmWriter {
     }
 
     fn finalize(&mut self) -> AnyResult<()> {
-        let encoder = self.encoder.as_ref()
-            .map_err(|e| anyhow!("{e}"))?;
+        let encoder = self.encoder;
 
         unsafe {
             let ok = FLAC__stream_encoder_finish(
@@ -458,6 +549,24 @@ impl PcmWriter for FlacPcmWriter {
     }
 }
 
+/// A path for libFLAC, which opens files with `fopen`.
+fn path_to_cstring(path: &Path) -> AnyResult<CString> {
+    // Any bytes but NUL are a valid path on unix.
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+    // libFLAC converts UTF-8 paths to wide strings on Windows.
+    #[cfg(not(unix))]
+    let bytes = path.to_str()
+        .ok_or_else(|| anyhow!("FLAC path is not UTF-8: {}", path.display()))?
+        .as_bytes();
+
+    CString::new(bytes)
+        .map_err(|_| anyhow!("path contains a NUL byte: {}", path.display()))
+}
+
 unsafe fn code_to_string(
     table: &[*const c_char; 0],
     code: u32,
@@ -0,0 +1,125 @@
+use rmx::prelude::*;
+use rmx::serde::{Serialize, Deserialize};
+use rmx::serde_json::{self, Value};
+use crate::types::Codec;
+use std::collections::BTreeMap;
+
+/// Codec-specific encoder options.
+#[derive(Serialize)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum EncoderSettings {
+    Wav,
+    Flac(FlacSettings),
+    Vorbis(VorbisSettings),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FlacSettings {
+    /// 0 (fastest) to 8 (smallest).
+    #[serde(default = "default_flac_compression_level")]
+    pub compression_level: u32,
+    /// Have libFLAC decode each frame as it is encoded and compare.
+    #[serde(default)]
+    pub verify: bool,
+    /// Frames per FLAC block, or `None` to let the compression level decide.
+    #[serde(default)]
+    pub blocksize: Option<u32>,
+    /// Bytes of padding to reserve for editing metadata later.
+    #[serde(default)]
+    pub padding: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+#[serde(deny_unknown_fields)]
+pub struct VorbisSettings {
+    /// -0.1 (smallest) to 1.0 (best).
+    #[serde(default = "default_vorbis_quality")]
+    pub quality: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WavSettings { }
+
+fn default_flac_compression_level() -> u32 { 5 }
+
+fn default_vorbis_quality() -> f32 { 0.5 }
+
+impl Default for FlacSettings {
+    fn default() -> FlacSettings {
+        FlacSettings {
+            compression_level: default_flac_compression_level(),
+            verify: false,
+            blocksize: None,
+            padding: None,
+        }
+    }
+}
+
+impl Default for VorbisSettings {
+    fn default() -> VorbisSettings {
+        VorbisSettings {
+            quality: default_vorbis_quality(),
+        }
+    }
+}
+
+impl EncoderSettings {
+    pub fn default_for(codec: Codec) -> EncoderSettings {
+        match codec {
+            Codec::Wav => EncoderSettings::Wav,
+            Codec::Flac => EncoderSettings::Flac(FlacSettings::default()),
+            Codec::Vorbis => EncoderSettings::Vorbis(VorbisSettings::default()),
+        }
+    }
+
+    /// Parse and validate the `encoder` table of a format in the config.
+    pub fn from_table(
+        codec: Codec,
+        table: &BTreeMap<String, Value>,
+    ) -> AnyResult<EncoderSettings> {
+        let value = Value::Object(table.clone().into_iter().collect());
+        let settings = match codec {
+            Codec::Wav => {
+                let WavSettings { } = serde_json::from_value(value)?;
+                EncoderSettings::Wav
+            }
+            Codec::Flac => EncoderSettings::Flac(serde_json::from_value(value)?),
+            Codec::Vorbis => EncoderSettings::Vorbis(serde_json::from_value(value)?),
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> AnyResult<()> {
+        match self {
+            EncoderSettings::Wav => { }
+            EncoderSettings::Flac(settings) => {
+                if settings.compression_level > 8 {
+                    bail!("compression_level must be 0 to 8, not {}", settings.compression_level);
+                }
+                if let Some(blocksize) = settings.blocksize
+                    && !(16..=65535).contains(&blocksize)
+                {
+                    bail!("blocksize must be 16 to 65535, not {blocksize}");
+                }
+            }
+            EncoderSettings::Vorbis(settings) => {
+                if !(-0.1..=1.0).contains(&settings.quality) {
+                    bail!("quality must be -0.1 to 1.0, not {}", settings.quality);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use rmx::prelude::*;
 use crate::types::{Format, BitDepth, SampleRate, Codec};
 use crate::io::{PcmReader, PcmWriter, Buf, Props};
+use crate::codecs::VorbisSettings;
 use std::path::Path;
 use std::io::{BufReader, BufWriter};
 use std::fs::File;
@@ -17,14 +18,20 @@ impl VorbisPcmReader {
 
 impl PcmReader for VorbisPcmReader {
     fn props(&mut self) -> AnyResult<Props> {
-        todo!()
+        bail!("Vorbis decoding is not implemented")
+    }
+
+    fn set_block_frames(&mut self, frames: usize) { }
+
+    fn total_frames(&mut self) -> AnyResult<Option<u64>> {
+        bail!("Vorbis decoding is not implemented")
     }
 
     fn read(
         &mut self,
         buf: &mut Buf,
     ) -> AnyResult<()> {
-        todo!()
+        bail!("Vorbis decoding is not implemented")
     }
 }
 
@@ -35,9 +42,10 @@ impl VorbisPcmWriter {
     pub fn new(
         path: &Path,
         props: Props,
-    ) -> VorbisPcmWriter {
+        settings: &VorbisSettings,
+    ) -> AnyResult<VorbisPcmWriter> {
         assert_eq!(props.format.codec, Codec::Vorbis);
-        todo!()
+        bail!("Vorbis encoding is not implemented")
     }
 }
 
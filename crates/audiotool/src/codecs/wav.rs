@@ -1,18 +1,64 @@
 use rmx::prelude::*;
 use crate::types::{Format, BitDepth, SampleRate, Codec};
-use crate::io::{PcmReader, PcmWriter, Buf, Props};
+use crate::io::{PcmReader, PcmWriter, Buf, Props, DEFAULT_BLOCK_FRAMES};
 use std::path::Path;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::fs::File;
 
 pub struct WavPcmReader {
-    reader: hound::Result<hound::WavReader<BufReader<File>>>,
+    reader: hound::Result<WavData>,
+    block_frames: usize,
+    /// Reused for the bytes of each block.
+    bytes: Vec<u8>,
+}
+
+/// A file positioned at its samples.
+///
+/// Hound reads the header; samples are read in blocks
+/// and decoded here.
+struct WavData {
+    spec: hound::WavSpec,
+    /// Bytes each sample is stored in,
+    /// which for 24-bit samples may be 4.
+    sample_bytes: usize,
+    total_samples: u64,
+    samples_left: u64,
+    file: BufReader<File>,
+}
+
+impl WavData {
+    fn open(path: &Path) -> hound::Result<WavData> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let total_samples = u64::from(reader.len());
+        let mut file = reader.into_inner();
+
+        // The data chunk's length is the field just before its first sample.
+        file.seek_relative(-4)?;
+        let mut data_len = [0; 4];
+        file.read_exact(&mut data_len)?;
+        let data_len = u64::from(u32::from_le_bytes(data_len));
+        let sample_bytes = match total_samples {
+            0 => usize::from(spec.bits_per_sample / 8),
+            total_samples => (data_len / total_samples) as usize,
+        };
+
+        Ok(WavData {
+            spec,
+            sample_bytes,
+            total_samples,
+            samples_left: total_samples,
+            file,
+        })
+    }
 }
 
 impl WavPcmReader {
     pub fn new(path: &Path) -> WavPcmReader {
         WavPcmReader {
-            reader: hound::WavReader::open(path),
+            reader: WavData::open(path),
+            block_frames: DEFAULT_BLOCK_FRAMES,
+            bytes: vec![],
         }
     }
 }
@@ -21,7 +67,7 @@ impl PcmReader for WavPcmReader {
     fn props(&mut self) -> AnyResult<Props> {
         let reader = self.reader.as_ref()
             .map_err(|e| anyhow!("{e}"))?;
-        let spec = reader.spec();
+        let spec = reader.spec;
         Ok(Props {
             channels: spec.channels,
             format: Format {
@@ -41,6 +87,17 @@ impl PcmReader for WavPcmReader {
         })
     }
 
+    fn set_block_frames(&mut self, frames: usize) {
+        assert!(frames > 0);
+        self.block_frames = frames;
+    }
+
+    fn total_frames(&mut self) -> AnyResult<Option<u64>> {
+        let reader = self.reader.as_ref()
+            .map_err(|e| anyhow!("{e}"))?;
+        Ok(Some(reader.total_samples / u64::from(reader.spec.channels)))
+    }
+
     fn read(
         &mut self,
         buf: &mut Buf,
@@ -48,74 +105,67 @@ impl PcmReader for WavPcmReader {
         let props = self.props()?;
         let reader = self.reader.as_mut()
             .map_err(|e| anyhow!("{e}"))?;
-        match props.format.bit_depth {
-            BitDepth::F32 => {
-                let bytes_to_read = 4096 * props.channels as usize;
-                let mut buf = buf.f32_mut();
+
+        let samples_to_read = (self.block_frames * props.channels as usize)
+            .min(reader.samples_left as usize);
+        self.bytes.resize(samples_to_read * reader.sample_bytes, 0);
+        reader.file.read_exact(&mut self.bytes)?;
+        reader.samples_left -= samples_to_read as u64;
+
+        let samples = self.bytes.chunks_exact(reader.sample_bytes);
+        match (props.format.bit_depth, reader.sample_bytes) {
+            (BitDepth::F32, 4) => {
+                let buf = buf.f32_mut();
                 buf.truncate(0);
-                buf.reserve_exact(bytes_to_read);
-                let mut samples = reader.samples::<f32>();
-                for _ in 0..bytes_to_read {
-                    match samples.next() {
-                        Some(sample) => {
-                            buf.push(sample?);
-                        }
-                        None => {
-                            break;
-                        }
-                    }
-                }
-                Ok(())
+                buf.extend(samples.map(|b| {
+                    f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+                }));
             }
-            BitDepth::I24 => {
-                let bytes_to_read = 4096 * props.channels as usize;
-                let mut buf = buf.i24_mut();
+            (BitDepth::I24, 3) => {
+                let buf = buf.i24_mut();
                 buf.truncate(0);
-                buf.reserve_exact(bytes_to_read);
-                let mut samples = reader.samples::<i32>();
-                for _ in 0..bytes_to_read {
-                    match samples.next() {
-                        Some(sample) => {
-                            buf.push(sample?);
-                        }
-                        None => {
-                            break;
-                        }
-                    }
-                }
-                Ok(())
+                buf.extend(samples.map(|b| {
+                    i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8
+                }));
             }
-            BitDepth::I16 => {
-                let bytes_to_read = 4096 * props.channels as usize;
-                let mut buf = buf.i16_mut();
+            (BitDepth::I24, 4) => {
+                // Only the low 24 bits are the sample.
+                let buf = buf.i24_mut();
                 buf.truncate(0);
-                buf.reserve_exact(bytes_to_read);
-                let mut samples = reader.samples::<i16>();
-                for _ in 0..bytes_to_read {
-                    match samples.next() {
-                        Some(sample) => {
-                            buf.push(sample?);
-                        }
-                        None => {
-                            break;
-                        }
-                    }
-                }
-                Ok(())
+                buf.extend(samples.map(|b| {
+                    i32::from_le_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8
+                }));
             }
-        }            
+            (BitDepth::I16, 2) => {
+                let buf = buf.i16_mut();
+                buf.truncate(0);
+                buf.extend(samples.map(|b| {
+                    i16::from_le_bytes([b[0], b[1]])
+                }));
+            }
+            (bit_depth, bytes) => {
+                bail!("unsupported sample width: {bit_depth:?} in {bytes} bytes");
+            }
+        }
+        Ok(())
     }
 }
 
 pub struct WavPcmWriter {
-    writer: Option<hound::Result<hound::WavWriter<BufWriter<File>>>>,
+    // `None` once finalized.
+    writer: Option<BufWriter<File>>,
+    /// Where the header ends and samples begin.
+    data_start: u64,
+    data_bytes: u64,
+    /// Reused for the bytes of each block.
+    bytes: Vec<u8>,
 }
 
 impl WavPcmWriter {
     pub fn new(
         path: &Path,
         props: Props,
-    ) -> WavPcmWriter {
+    ) -> AnyResult<WavPcmWriter> {
         assert_eq!(props.format.codec, Codec::Wav);
         let spec = hound::WavSpec {
             channels: props.channels,
@@ -131,9 +181,19 @@ impl WavPcmWriter {
                 BitDepth::I16 => hound::SampleFormat::Int,
             },
         };
-        WavPcmWriter {
-            writer: Some(hound::WavWriter::create(path, spec)),
-        }
+
+        // Hound writes the header for no samples,
+        // and `finalize` fixes its lengths once the samples are written.
+        let mut writer = BufWriter::new(File::create(path)?);
+        hound::WavWriter::new(&mut writer, spec)?.finalize()?;
+        let data_start = writer.stream_position()?;
+
+        Ok(WavPcmWriter {
+            writer: Some(writer),
+            data_start,
+            data_bytes: 0,
+            bytes: vec![],
+        })
     }
 }
 
@@ -144,26 +204,38 @@ impl PcmWriter for WavPcmWriter {
     ) -> AnyResult<()> {
         match &mut self.writer {
             Some(writer) => {
-                let writer = writer.as_mut()
-                    .map_err(|e| anyhow!("{e}"))?;
                 match buf {
                     Buf::F32(buf) => {
-                        for sample in buf.iter().copied() {
-                            writer.write_sample(sample)?;
+                        self.bytes.resize(buf.len() * 4, 0);
+                        for (bytes, sample) in self.bytes.chunks_exact_mut(4).zip(buf) {
+                            bytes.copy_from_slice(&sample.to_le_bytes());
                         }
                     }
                     Buf::I24(buf) => {
-                        for sample in buf.iter().copied() {
-                            writer.write_sample(sample)?;
+                        self.bytes.resize(buf.len() * 3, 0);
+                        for (bytes, sample) in self.bytes.chunks_exact_mut(3).zip(buf) {
+                            if !(-(1 << 23)..(1 << 23)).contains(sample) {
+                                bail!("sample out of 24-bit range: {sample}");
+                            }
+                            bytes.copy_from_slice(&sample.to_le_bytes()[..3]);
                         }
                     }
                     Buf::I16(buf) => {
-                        for sample in buf.iter().copied() {
-                            writer.write_sample(sample)?;
+                        self.bytes.resize(buf.len() * 2, 0);
+                        for (bytes, sample) in self.bytes.chunks_exact_mut(2).zip(buf) {
+                            bytes.copy_from_slice(&sample.to_le_bytes());
                         }
                     }
                     Buf::Uninit => panic!(),
                 }
+
+                let data_bytes = self.data_bytes + self.bytes.len() as u64;
+                // The RIFF length counts everything after itself.
+                if self.data_start - 8 + data_bytes > u64::from(u32::MAX) {
+                    bail!("too much audio for a WAV file");
+                }
+                writer.write_all(&self.bytes)?;
+                self.data_bytes = data_bytes;
                 Ok(())
             }
             None => {
@@ -175,10 +247,14 @@ impl PcmWriter for WavPcmWriter {
     fn finalize(&mut self) -> AnyResult<()> {
         let writer = std::mem::replace(&mut self.writer, None);
         match writer {
-            Some(writer) => {
-                let writer = writer
-                    .map_err(|e| anyhow!("{e}"))?;
-                writer.finalize()?;
+            Some(mut writer) => {
+                let riff_len = self.data_start - 8 + self.data_bytes;
+                writer.seek(SeekFrom::Start(4))?;
+                writer.write_all(&(riff_len as u32).to_le_bytes())?;
+                // The data chunk's length is the field just before its first sample.
+                writer.seek(SeekFrom::Start(self.data_start - 4))?;
+                writer.write_all(&(self.data_bytes as u32).to_le_bytes())?;
+                writer.flush()?;
                 Ok(())
             }
             None => {
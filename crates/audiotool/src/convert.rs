@@ -3,22 +3,226 @@ pub mod config {
     use std::path::PathBuf;
     use rmx::serde::{Serialize, Deserialize};
     use crate::types::{Format, Codec, BitDepth, SampleRate};
+    use crate::dsp::DspStage;
+    use crate::codecs::EncoderSettings;
+    use crate::io::DEFAULT_BLOCK_FRAMES;
+    use rmx::serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+    use std::num::NonZeroUsize;
+    use std::iter;
 
     #[derive(Serialize, Deserialize)]
     #[derive(Clone)]
     pub struct Config {
         pub reference_tracks_dir: PathBuf,
+        /// Matched against the full path of files under every input root.
+        ///
+        /// Empty matches everything.
+        #[serde(default)]
         pub reference_track_regex: String,
+        /// Gitignore-style patterns, relative to `reference_tracks_dir`.
+        /// If there are any, only matching files are converted.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub include: Vec<String>,
+        /// Gitignore-style patterns of files under `reference_tracks_dir`
+        /// not to convert.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub exclude: Vec<String>,
+        /// More input roots, converted to the same formats.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub inputs: Vec<InputRoot>,
+        /// Follow symlinks to input files and directories.
+        ///
+        /// Links back to a directory being walked are skipped with a warning.
+        /// Root directories are followed either way.
+        #[serde(default)]
+        pub follow_symlinks: bool,
         pub out_root_dir: PathBuf,
+        /// A Tera template for output paths, with the variables
+        /// `out_root_dir`, `relative_path`, `file_stem`, `format_ext`,
+        /// `codec`, `sample_rate` (Hz), `bit_depth` (bits),
+        /// `channels` (of the input), and `tags`,
+        /// a map of the input's lowercase tag names, e.g. `tags.artist`.
         pub out_path_template: String,
-        pub formats: Vec<Format>,
+        pub formats: Vec<FormatConfig>,
+        /// Decode each output before moving it into place,
+        /// checking lossless outputs sample-for-sample.
+        #[serde(default)]
+        pub verify: bool,
+        #[serde(default)]
+        pub incremental: Incremental,
+        /// What to do about existing outputs that `incremental`
+        /// doesn't consider up to date.
+        #[serde(default)]
+        pub overwrite: OverwritePolicy,
+        #[serde(flatten)]
+        pub limits: Limits,
+    }
+
+    /// Limits on the resources used converting at once.
+    #[derive(Serialize, Deserialize)]
+    #[derive(Copy, Clone)]
+    #[derive(Debug)]
+    #[derive(Default)]
+    pub struct Limits {
+        /// How many input files to convert at once,
+        /// and how many threads convert their outputs.
+        ///
+        /// Defaults to the number of CPUs.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub jobs: Option<NonZeroUsize>,
+        /// Roughly how many bytes of audio buffers may be in use at once,
+        /// across all files being converted.
+        ///
+        /// This is estimated from the number of outputs of each file,
+        /// and a file is always allowed to start if no others are running.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_buffer_memory: Option<u64>,
+        /// Frames per block read from each input,
+        /// and so roughly per buffer through the conversion.
+        ///
+        /// Larger blocks use more memory, but have less overhead.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub block_frames: Option<NonZeroUsize>,
+    }
+
+    impl Limits {
+        pub fn block_frames(&self) -> usize {
+            self.block_frames
+                .map(NonZeroUsize::get)
+                .unwrap_or(DEFAULT_BLOCK_FRAMES)
+        }
+    }
+
+    /// A directory of input files.
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone)]
+    #[derive(Debug)]
+    pub struct InputRoot {
+        pub dir: PathBuf,
+        /// Where outputs of this root go, relative to `out_root_dir`.
+        ///
+        /// This is the start of `relative_path` in `out_path_template`.
+        #[serde(default)]
+        pub out_dir: PathBuf,
+        /// Gitignore-style patterns, relative to `dir`.
+        /// If there are any, only matching files are converted.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub include: Vec<String>,
+        /// Gitignore-style patterns of files not to convert.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub exclude: Vec<String>,
+    }
+
+    /// How the planner decides an existing output is up to date.
+    #[derive(Serialize, Deserialize)]
+    #[derive(Eq, PartialEq)]
+    #[derive(Copy, Clone)]
+    #[derive(Debug)]
+    #[derive(Default)]
+    pub enum Incremental {
+        /// Always reconvert.
+        Off,
+        /// Skip outputs newer than their input.
+        #[default]
+        Mtime,
+        /// Skip outputs whose input content and conversion settings
+        /// match those recorded in the manifest.
+        Hash,
+    }
+
+    /// What the planner does when an output it would write already exists.
+    #[derive(Serialize, Deserialize)]
+    #[derive(Eq, PartialEq)]
+    #[derive(Copy, Clone)]
+    #[derive(Debug)]
+    #[derive(Default)]
+    pub enum OverwritePolicy {
+        /// Replace the existing file.
+        #[default]
+        Overwrite,
+        /// Leave the existing file and don't convert.
+        Skip,
+        /// Fail the plan, listing every existing output.
+        Error,
+        /// Write beside the existing file, with a numeric suffix,
+        /// like `song-1.flac`.
+        KeepBoth,
+    }
+
+    impl FromStr for OverwritePolicy {
+        type Err = AnyError;
+
+        fn from_str(s: &str) -> AnyResult<OverwritePolicy> {
+            match s {
+                "overwrite" => Ok(OverwritePolicy::Overwrite),
+                "skip" => Ok(OverwritePolicy::Skip),
+                "error" => Ok(OverwritePolicy::Error),
+                "keep-both" => Ok(OverwritePolicy::KeepBoth),
+                _ => bail!("unknown overwrite policy `{s}`: expected overwrite, skip, error or keep-both"),
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone)]
+    pub struct FormatConfig {
+        #[serde(flatten)]
+        pub format: Format,
+        /// DSP stages run in order, in `F32`, before sample rate conversion.
+        #[serde(default)]
+        pub dsp: Vec<DspStage>,
+        /// Overrides `Config::out_path_template` for this format.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub out_path_template: Option<String>,
+        /// Codec-specific options, like FLAC `compression_level`.
+        ///
+        /// Checked against the codec by the planner.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub encoder: BTreeMap<String, Value>,
+    }
+
+    impl From<Format> for FormatConfig {
+        fn from(format: Format) -> FormatConfig {
+            FormatConfig {
+                format,
+                dsp: vec![],
+                out_path_template: None,
+                encoder: BTreeMap::new(),
+            }
+        }
+    }
+
+    impl FormatConfig {
+        pub fn encoder_settings(&self) -> AnyResult<EncoderSettings> {
+            EncoderSettings::from_table(self.format.codec, &self.encoder)
+                .with_context(|| format!("invalid encoder settings for {:?}", self.format.codec))
+        }
     }
 
     impl Config {
+        /// `reference_tracks_dir`, then `inputs`.
+        pub fn input_roots(&self) -> Vec<InputRoot> {
+            let primary = InputRoot {
+                dir: self.reference_tracks_dir.clone(),
+                out_dir: PathBuf::new(),
+                include: self.include.clone(),
+                exclude: self.exclude.clone(),
+            };
+            iter::once(primary)
+                .chain(self.inputs.iter().cloned())
+                .collect()
+        }
+
         pub fn template() -> Config {
             Config {
                 reference_tracks_dir: S("./in/").into(),
                 reference_track_regex: S("\\.wav"),
+                include: vec![],
+                exclude: vec![],
+                inputs: vec![],
+                follow_symlinks: false,
                 out_root_dir: S("./out/").into(),
                 out_path_template: S("{{out_root_dir}}/{{relative_path}}/{{file_stem}}.{{format_ext}}"),
                 formats: vec![
@@ -26,8 +230,153 @@ pub mod config {
                         codec: Codec::Wav,
                         bit_depth: BitDepth::I24,
                         sample_rate: SampleRate::K48,
-                    },
-                ]
+                    }.into(),
+                ],
+                verify: false,
+                incremental: Incremental::Mtime,
+                overwrite: OverwritePolicy::Overwrite,
+                limits: Limits::default(),
+            }
+        }
+    }
+}
+
+pub mod manifest {
+    use rmx::prelude::*;
+    use rmx::serde::{Serialize, Deserialize};
+    use crate::types::Format;
+    use crate::dsp::DspStage;
+    use crate::stats::OutputStats;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+    use std::fs;
+
+    pub const MANIFEST_FILE_NAME: &str = ".audiotool-manifest.json";
+
+    /// A record of what produced each output, keyed by output path,
+    /// stored as JSON in the output root directory.
+    ///
+    /// Each run updates the entries for the outputs it converted,
+    /// and leaves the rest.
+    #[derive(Serialize, Deserialize)]
+    #[derive(Default)]
+    #[derive(Clone)]
+    #[derive(Debug)]
+    pub struct Manifest {
+        pub outputs: BTreeMap<PathBuf, ManifestEntry>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[derive(Clone)]
+    #[derive(Debug)]
+    pub struct ManifestEntry {
+        pub in_path: PathBuf,
+        /// `None` if the input couldn't be hashed.
+        pub input_hash: Option<String>,
+        /// The size and modification time of the input when it was hashed,
+        /// so later runs can reuse the hash while they are unchanged.
+        #[serde(default)]
+        pub input_size: Option<u64>,
+        #[serde(default)]
+        pub input_mtime: Option<SystemTime>,
+        pub format: Format,
+        pub dsp: Vec<DspStage>,
+        pub settings_hash: String,
+        pub tool_version: String,
+        pub stats: Option<OutputStats>,
+        pub success: bool,
+        pub error: Option<String>,
+    }
+
+    pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+    impl Manifest {
+        pub fn path(out_root_dir: &Path) -> PathBuf {
+            out_root_dir.join(MANIFEST_FILE_NAME)
+        }
+
+        /// Load a manifest, or an empty one if it doesn't exist.
+        pub fn load(path: &Path) -> AnyResult<Manifest> {
+            let json = match fs::read_to_string(path) {
+                Ok(json) => json,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(Manifest::default());
+                }
+                Err(e) => {
+                    return Err(e).context(format!("reading manifest {}", path.display()));
+                }
+            };
+            let manifest = rmx::serde_json::from_str(&json)
+                .context(format!("parsing manifest {}", path.display()))?;
+            Ok(manifest)
+        }
+
+        /// Outputs or inputs with paths that aren't UTF-8
+        /// can't be stored in JSON, and are left out.
+        pub fn save(&self, path: &Path) -> AnyResult<()> {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let (outputs, unrecordable): (BTreeMap<_, _>, BTreeMap<_, _>) = self.outputs.iter()
+                .partition(|(out_path, entry)| {
+                    out_path.to_str().is_some() && entry.in_path.to_str().is_some()
+                });
+            for out_path in unrecordable.keys() {
+                warn!("not recording non-UTF-8 path in manifest: {}", out_path.display());
+            }
+            let json = rmx::serde_json::to_string_pretty(&Manifest {
+                outputs: outputs.into_iter()
+                    .map(|(out_path, entry)| (out_path.clone(), entry.clone()))
+                    .collect(),
+            })?;
+            let tmp_path = path.with_extension("json.tmp");
+            fs::write(&tmp_path, json)?;
+            fs::rename(&tmp_path, path)?;
+            Ok(())
+        }
+    }
+
+    pub fn hash_file(path: &Path) -> AnyResult<String> {
+        let file = fs::File::open(path)?;
+        let mut hasher = rmx::blake3::Hasher::new();
+        hasher.update_reader(file)?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// The size and modification time of a file.
+    pub fn file_stamp(path: &Path) -> AnyResult<(u64, SystemTime)> {
+        let metadata = fs::metadata(path)?;
+        Ok((metadata.len(), metadata.modified()?))
+    }
+
+    impl Manifest {
+        /// The hash of the input at `in_path`.
+        ///
+        /// Reuses the hash recorded by any of `out_paths`
+        /// if the input's `stamp`, from `file_stamp`,
+        /// is the size and modification time it had when hashed,
+        /// and otherwise reads the whole input.
+        pub fn input_hash<'a>(
+            &self,
+            in_path: &Path,
+            stamp: Option<(u64, SystemTime)>,
+            out_paths: impl IntoIterator<Item = &'a Path>,
+        ) -> AnyResult<String> {
+            let recorded = stamp.and_then(|(size, mtime)| {
+                out_paths.into_iter()
+                    .filter_map(|out_path| self.outputs.get(out_path))
+                    .find(|entry| {
+                        entry.in_path == in_path
+                            && entry.input_size == Some(size)
+                            && entry.input_mtime == Some(mtime)
+                            && entry.input_hash.is_some()
+                    })
+                    .and_then(|entry| entry.input_hash.clone())
+            });
+            match recorded {
+                Some(input_hash) => Ok(input_hash),
+                None => hash_file(in_path),
             }
         }
     }
@@ -36,26 +385,45 @@ pub mod config {
 pub mod plan {
     use rmx::prelude::*;
     use rmx::rayon::{self, prelude::*};
-    use rmx::regex::Regex;
+    use rmx::regex::bytes::Regex;
+    use rmx::serde::Serialize;
 
-    use super::config::Config;
-    use crate::types::Format;
-    use super::OutFile;
+    use super::config::{Config, InputRoot, Incremental, OverwritePolicy, Limits};
+    use super::manifest::{self, Manifest};
+    use crate::types::{Format, Codec, BitDepth, SampleRate};
+    use crate::io::Props;
+    use crate::codecs::{self, EncoderSettings};
+    use super::{OutFile, OutFileAction, SkipReason, OutputSpecs};
 
     use rmx::walkdir::{self, WalkDir, DirEntry};
+    use rmx::ignore::gitignore::{Gitignore, GitignoreBuilder};
     use std::sync::mpsc::{SyncSender, Receiver, sync_channel, TryRecvError};
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf, Component};
+    use std::collections::BTreeMap;
+    use std::cell::Cell;
     use std::thread;
+    use std::fs;
 
+    #[derive(Serialize)]
     #[derive(Debug)]
     pub struct Plan {
         pub outputs: Vec<InfilePlan>,
+        pub verify: bool,
+        pub limits: Limits,
+        /// Where to record converted outputs.
+        pub manifest_path: Option<PathBuf>,
+        /// The manifest from the previous run.
+        #[serde(skip)]
+        pub manifest: Manifest,
     }
 
+    #[derive(Serialize)]
     #[derive(Debug)]
     pub struct InfilePlan {
         pub infile: PathBuf,
         pub outfiles: Vec<OutFile>,
+        /// Hash of the infile content, in `Incremental::Hash` mode.
+        pub input_hash: Option<String>,
     }
 
     pub enum Request {
@@ -93,57 +461,654 @@ pub mod plan {
         config: Config,
         rx: Receiver<Request>,
     ) -> AnyResult<Option<Plan>> {
-        let regex = Regex::new(&config.reference_track_regex)?;
-        let mut outputs = Vec::new();
-
-        // nb: supports symlink root dir, but not following symlinks
-        let walkdir = WalkDir::new(&config.reference_tracks_dir)
-            .into_iter();
+        let roots = config.input_roots();
+        let filters = roots.iter()
+            .map(InputFilter::new)
+            .collect::<AnyResult<Vec<_>>>()?;
+
+        let walkdirs = roots.iter().zip(&filters).flat_map(|(root, filter)| {
+            WalkDir::new(&root.dir)
+                .follow_links(config.follow_symlinks)
+                .into_iter()
+                .filter_entry(move |entry| !filter.is_excluded_dir(entry))
+                .map(move |entry| (root, filter, entry))
+        });
 
-        for entry in walkdir {
-            match rx.try_recv() {
-                Ok(Request::Cancel) | Err(TryRecvError::Disconnected) => {
-                    return Ok(None);
+        let cancelled = Cell::new(false);
+        let candidates = walkdirs
+            .take_while(|_| {
+                match rx.try_recv() {
+                    Ok(Request::Cancel) | Err(TryRecvError::Disconnected) => {
+                        cancelled.set(true);
+                        false
+                    }
+                    Err(TryRecvError::Empty) => true,
                 }
-                Err(TryRecvError::Empty) => {
-                    // nop
+            })
+            .filter_map(|(root, filter, entry)| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) if e.loop_ancestor().is_some() => {
+                        warn!("not following symlink cycle: {e}");
+                        return None;
+                    }
+                    Err(e) => return Some(Err(e.into())),
+                };
+
+                // Symlinks are only files or directories if followed.
+                if !entry.file_type().is_file() {
+                    return None;
                 }
+
+                Some(Ok((root, filter, entry.into_path())))
+            });
+
+        let plan = plan_inputs(&config, candidates);
+        if cancelled.get() {
+            return Ok(None);
+        }
+        plan.map(Some)
+    }
+
+    /// A plan for only `paths`, like files that changed since the last run.
+    ///
+    /// Paths that aren't files under an input root,
+    /// or aren't selected by the config, are left out.
+    ///
+    /// Paths may name files differently than the roots do,
+    /// like a watcher's absolute paths under a relative root,
+    /// so they are matched to roots canonically,
+    /// and planned under the name a walk of the root would give them.
+    pub fn for_inputs(config: &Config, paths: &[PathBuf]) -> AnyResult<Plan> {
+        let roots = config.input_roots();
+        let filters = roots.iter()
+            .map(InputFilter::new)
+            .collect::<AnyResult<Vec<_>>>()?;
+
+        let canonical_roots = roots.iter()
+            .map(|root| fs::canonicalize(&root.dir).ok())
+            .collect::<Vec<_>>();
+
+        let candidates = paths.iter().filter_map(|path| {
+            let canonical_path = canonicalize_parent(path)?;
+            let (root, filter, infile) = roots.iter().zip(&filters).zip(&canonical_roots)
+                .find_map(|((root, filter), canonical_root)| {
+                    let relative = canonical_path.strip_prefix(canonical_root.as_ref()?).ok()?;
+                    Some((root, filter, root.dir.join(relative)))
+                })?;
+
+            let metadata = match config.follow_symlinks {
+                true => fs::metadata(&infile),
+                false => fs::symlink_metadata(&infile),
+            };
+            if !metadata.map(|metadata| metadata.is_file()).unwrap_or(false) {
+                return None;
             }
 
-            let entry = entry?;
+            Some(Ok((root, filter, infile)))
+        });
 
-            // nb: this doesn't support symlinked input files
-            if !entry.file_type().is_file() {
+        plan_inputs(config, candidates)
+    }
+
+    /// Plan the candidate inputs the config selects,
+    /// each a file under an input root, with that root's filter.
+    fn plan_inputs<'r>(
+        config: &Config,
+        candidates: impl Iterator<Item = AnyResult<(&'r InputRoot, &'r InputFilter, PathBuf)>>,
+    ) -> AnyResult<Plan> {
+        let regex = Regex::new(&config.reference_track_regex)?;
+        let output_specs = config.output_specs()?;
+        let mut outputs = Vec::new();
+
+        let manifest_path = Manifest::path(&config.out_root_dir);
+        let manifest = Manifest::load(&manifest_path)?;
+        // Outputs refused by `OverwritePolicy::Error`.
+        let mut existing = Vec::new();
+
+        for candidate in candidates {
+            let (root, filter, infile) = candidate?;
+
+            if !is_selected(&regex, filter, &infile) {
                 continue;
             }
 
-            let infile = entry.path();
-            
-            match infile.to_str() {
-                Some(infile) => {
-                    if !regex.is_match(infile) {
-                        continue;
+            outputs.push(plan_infile(
+                config,
+                &output_specs,
+                &manifest,
+                root,
+                &infile,
+                &mut existing,
+            )?);
+        }
+
+        check_existing(&existing)?;
+        check_collisions(&outputs)?;
+
+        Ok(Plan {
+            outputs,
+            verify: config.verify,
+            limits: config.limits,
+            manifest_path: Some(manifest_path),
+            manifest,
+        })
+    }
+
+    /// `path` with its directory canonicalized,
+    /// leaving a symlink at `path` itself unresolved.
+    fn canonicalize_parent(path: &Path) -> Option<PathBuf> {
+        let file_name = path.file_name()?;
+        let parent = match path.parent()? {
+            parent if parent.as_os_str().is_empty() => Path::new("."),
+            parent => parent,
+        };
+        Some(fs::canonicalize(parent).ok()?.join(file_name))
+    }
+
+    fn is_selected(regex: &Regex, filter: &InputFilter, infile: &Path) -> bool {
+        regex.is_match(infile.as_os_str().as_encoded_bytes())
+            && filter.is_selected(infile)
+    }
+
+    fn plan_infile(
+        config: &Config,
+        output_specs: &OutputSpecs,
+        manifest: &Manifest,
+        root: &InputRoot,
+        infile: &Path,
+        existing: &mut Vec<PathBuf>,
+    ) -> AnyResult<InfilePlan> {
+        let mut outfiles = output_specs.outputs_for(root, infile);
+
+        // Inputs that can't be read fail only their own outputs.
+        let input_hash = match config.incremental {
+            Incremental::Hash => {
+                let stamp = manifest::file_stamp(infile).ok();
+                let out_paths = outfiles.iter().map(|outfile| outfile.path.as_path());
+                match manifest.input_hash(infile, stamp, out_paths) {
+                    Ok(input_hash) => Some(input_hash),
+                    Err(e) => {
+                        fail_outfiles(&mut outfiles, e.context("error hashing input"));
+                        None
                     }
                 }
-                None => {
-                    todo!("non-utf8 infile");
+            }
+            _ => None,
+        };
+
+        for outfile in &mut outfiles {
+            if outfile.error.is_some() {
+                continue;
+            }
+            let action = outfile_action(
+                config.incremental,
+                manifest,
+                infile,
+                input_hash.as_deref(),
+                outfile,
+            );
+            match action {
+                Ok(action) => outfile.action = action,
+                Err(e) => {
+                    outfile.error = Some(format!("{e:#}"));
+                    continue;
                 }
             }
+            apply_overwrite_policy(config.overwrite, outfile, existing);
+        }
 
-            let outfiles: AnyResult<Vec<_>> = config.outputs_for(&infile).collect();
-            let outfiles = outfiles?;
+        Ok(InfilePlan {
+            infile: infile.to_owned(),
+            outfiles,
+            input_hash,
+        })
+    }
+
+    /// Give `outfiles` that don't already have an error `e`.
+    fn fail_outfiles(outfiles: &mut [OutFile], e: AnyError) {
+        for outfile in outfiles.iter_mut().filter(|outfile| outfile.error.is_none()) {
+            outfile.error = Some(format!("{e:#}"));
+        }
+    }
 
-            // todo check if outfile already exists
+    /// The include and exclude patterns of an input root.
+    struct InputFilter {
+        /// `None` to include everything.
+        include: Option<Gitignore>,
+        exclude: Gitignore,
+    }
+
+    impl InputFilter {
+        fn new(root: &InputRoot) -> AnyResult<InputFilter> {
+            let build = |patterns: &[String]| -> AnyResult<Gitignore> {
+                let mut builder = GitignoreBuilder::new(&root.dir);
+                for pattern in patterns {
+                    builder.add_line(None, pattern)
+                        .with_context(|| format!("invalid pattern `{pattern}`"))?;
+                }
+                Ok(builder.build()?)
+            };
 
-            outputs.push(InfilePlan {
-                infile: infile.to_owned(),
-                outfiles,
+            Ok(InputFilter {
+                include: match root.include.is_empty() {
+                    true => None,
+                    false => Some(build(&root.include)?),
+                },
+                exclude: build(&root.exclude)?,
             })
         }
 
-        Ok(Some(Plan {
+        /// Excluded directories aren't walked at all.
+        fn is_excluded_dir(&self, entry: &DirEntry) -> bool {
+            entry.depth() > 0
+                && entry.file_type().is_dir()
+                && self.exclude.matched(entry.path(), true).is_ignore()
+        }
+
+        /// Patterns match like `.gitignore` lines:
+        /// a pattern naming a directory matches everything in it.
+        fn is_selected(&self, path: &Path) -> bool {
+            let included = match &self.include {
+                Some(include) => include.matched_path_or_any_parents(path, false).is_ignore(),
+                None => true,
+            };
+            included && !self.exclude.matched_path_or_any_parents(path, false).is_ignore()
+        }
+    }
+
+    /// A plan to convert one file to one output,
+    /// outside of any configured tree.
+    ///
+    /// The output codec is chosen by the extension of `outfile`,
+    /// and the bit depth and sample rate default to the input's.
+    pub fn single_file(
+        infile: &Path,
+        outfile: &Path,
+        bit_depth: Option<BitDepth>,
+        sample_rate: Option<SampleRate>,
+        overwrite: OverwritePolicy,
+    ) -> AnyResult<Plan> {
+        let ext = outfile.extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| anyhow!("output path has no extension: {}", outfile.display()))?;
+        let codec = Codec::from_ext(ext)
+            .ok_or_else(|| anyhow!("unknown output extension: `{ext}`"))?;
+
+        let source_props = codecs::probe(infile)?;
+        let bit_depth = bit_depth.unwrap_or(match (codec, source_props.format.bit_depth) {
+            // FLAC can't store floats.
+            (Codec::Flac, BitDepth::F32) => BitDepth::I24,
+            (_, bit_depth) => bit_depth,
+        });
+        let format = Format {
+            codec,
+            bit_depth,
+            sample_rate: sample_rate.unwrap_or(source_props.format.sample_rate),
+        };
+
+        // Checked as the writer would, so bad options fail before converting.
+        let props = Props {
+            channels: source_props.channels,
+            format,
+        };
+        if !props.is_usable() {
+            bail!(
+                "unsupported output {}: {} channels of {:?} {:?}",
+                outfile.display(),
+                props.channels,
+                props.format.codec,
+                props.format.bit_depth,
+            );
+        }
+
+        let action = if outfile.exists() {
+            OutFileAction::Overwrite
+        } else {
+            OutFileAction::Create
+        };
+        let mut outfile = OutFile {
+            path: outfile.to_owned(),
+            format,
+            dsp: vec![],
+            encoder: EncoderSettings::default_for(codec),
+            action,
+            error: None,
+        };
+        let mut existing = Vec::new();
+        apply_overwrite_policy(overwrite, &mut outfile, &mut existing);
+        check_existing(&existing)?;
+
+        let outputs = vec![InfilePlan {
+            infile: infile.to_owned(),
+            outfiles: vec![outfile],
+            input_hash: None,
+        }];
+
+        check_collisions(&outputs)?;
+
+        Ok(Plan {
             outputs,
-        }))
+            verify: false,
+            limits: Limits::default(),
+            manifest_path: None,
+            manifest: Manifest::default(),
+        })
+    }
+
+    /// Decide what to do about an output that would overwrite a file.
+    ///
+    /// Outputs refused by `OverwritePolicy::Error` are added to `existing`.
+    fn apply_overwrite_policy(
+        policy: OverwritePolicy,
+        outfile: &mut OutFile,
+        existing: &mut Vec<PathBuf>,
+    ) {
+        if outfile.action != OutFileAction::Overwrite {
+            return;
+        }
+
+        match policy {
+            OverwritePolicy::Overwrite => { }
+            OverwritePolicy::Skip => {
+                outfile.action = OutFileAction::Skip(SkipReason::Exists);
+            }
+            OverwritePolicy::Error => {
+                existing.push(outfile.path.clone());
+            }
+            OverwritePolicy::KeepBoth => {
+                outfile.path = keep_both_path(&outfile.path);
+                outfile.action = OutFileAction::KeepBoth;
+            }
+        }
+    }
+
+    /// The path `keep_both_path` could have made `path` from,
+    /// `stem.ext` for `stem-N.ext`.
+    pub(super) fn keep_both_original(path: &Path) -> Option<PathBuf> {
+        let stem = path.file_stem()?.to_str()?;
+        let (original_stem, n) = stem.rsplit_once('-')?;
+        if original_stem.is_empty() || n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut file_name = original_stem.to_string();
+        if let Some(ext) = path.extension() {
+            file_name.push('.');
+            file_name.push_str(ext.to_str()?);
+        }
+        Some(path.with_file_name(file_name))
+    }
+
+    /// The first of `stem-1.ext`, `stem-2.ext`, ... that doesn't exist.
+    fn keep_both_path(path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default();
+        (1..).map(|n| {
+            let mut file_name = stem.to_os_string();
+            file_name.push(format!("-{n}"));
+            if let Some(ext) = path.extension() {
+                file_name.push(".");
+                file_name.push(ext);
+            }
+            path.with_file_name(file_name)
+        }).find(|path| !path.exists()).expect("unbounded")
+    }
+
+    fn check_existing(existing: &[PathBuf]) -> AnyResult<()> {
+        if !existing.is_empty() {
+            let paths = existing.iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>();
+            bail!(
+                "{} outputs already exist and overwrite policy is `error`:\n  {}",
+                paths.len(),
+                paths.join("\n  "),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fail if two outputs would be written to the same path,
+    /// or an output would overwrite an input,
+    /// listing every offending pair.
+    fn check_collisions(outputs: &[InfilePlan]) -> AnyResult<()> {
+        let inputs: BTreeMap<PathBuf, &Path> = outputs.iter()
+            .map(|input| (collision_key(&input.infile), input.infile.as_path()))
+            .collect();
+
+        // Each output path and the input it's converted from.
+        let mut writers: BTreeMap<PathBuf, Vec<(&Path, &Path)>> = BTreeMap::new();
+        for input in outputs {
+            // Outputs that failed to plan won't be written.
+            for outfile in input.outfiles.iter().filter(|outfile| outfile.error.is_none()) {
+                writers.entry(collision_key(&outfile.path))
+                    .or_default()
+                    .push((&input.infile, &outfile.path));
+            }
+        }
+
+        let mut report = vec![];
+        for (key, writers) in &writers {
+            let (first_infile, first_outfile) = writers[0];
+            for (infile, outfile) in &writers[1..] {
+                report.push(format!(
+                    "{} -> {} collides with {} -> {}",
+                    first_infile.display(), first_outfile.display(),
+                    infile.display(), outfile.display(),
+                ));
+            }
+            if let Some(overwritten) = inputs.get(key) {
+                for (infile, outfile) in writers {
+                    report.push(format!(
+                        "{} -> {} overwrites input {}",
+                        infile.display(), outfile.display(),
+                        overwritten.display(),
+                    ));
+                }
+            }
+        }
+
+        if !report.is_empty() {
+            bail!(
+                "{} output path collisions; check out_path_template:\n  {}",
+                report.len(),
+                report.join("\n  "),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Paths that name the same file map to the same key,
+    /// as far as we can tell without creating anything.
+    fn collision_key(path: &Path) -> PathBuf {
+        // Existing files may be reached through symlinks or `..`.
+        if let Ok(path) = fs::canonicalize(path) {
+            return path;
+        }
+
+        let mut key = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => { }
+                Component::ParentDir if key.file_name().is_some() => {
+                    key.pop();
+                }
+                component => key.push(component),
+            }
+        }
+        key
+    }
+
+    fn outfile_action(
+        incremental: Incremental,
+        manifest: &Manifest,
+        infile: &Path,
+        input_hash: Option<&str>,
+        outfile: &OutFile,
+    ) -> AnyResult<OutFileAction> {
+        let out_metadata = match fs::metadata(&outfile.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(OutFileAction::Create);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match incremental {
+            Incremental::Off => { }
+            Incremental::Mtime => {
+                let in_modified = fs::metadata(infile)?.modified()?;
+                let out_modified = out_metadata.modified()?;
+                if out_modified > in_modified {
+                    return Ok(OutFileAction::Skip(SkipReason::UpToDate));
+                }
+            }
+            Incremental::Hash => {
+                let entry = manifest.outputs.get(&outfile.path);
+                if let (Some(entry), Some(input_hash)) = (entry, input_hash)
+                    && entry.success
+                    && entry.input_hash.as_deref() == Some(input_hash)
+                    && entry.settings_hash == outfile.settings_hash()
+                {
+                    return Ok(OutFileAction::Skip(SkipReason::Unchanged));
+                }
+            }
+        }
+
+        Ok(OutFileAction::Overwrite)
+    }
+}
+
+pub mod clean {
+    use rmx::prelude::*;
+    use rmx::walkdir::WalkDir;
+
+    use super::config::Config;
+    use super::manifest::Manifest;
+    use super::plan::{Plan, keep_both_original};
+    use crate::types::Format;
+
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::ffi::OsStr;
+    use std::path::{Path, PathBuf};
+    use std::fs;
+
+    /// Files under `out_root_dir` that the plan would not produce.
+    ///
+    /// Only files with the extension of a configured format are considered,
+    /// and input files are never included.
+    /// Nor are outputs kept beside an expected one
+    /// by `OverwritePolicy::KeepBoth`,
+    /// which the manifest records as made from the same input.
+    ///
+    /// Fails if planning failed for some output,
+    /// as its path may not be known, and the output mistaken for stale.
+    pub fn stale_outputs(config: &Config, plan: &Plan) -> AnyResult<Vec<PathBuf>> {
+        let unplanned = plan.outputs.iter()
+            .flat_map(|infile_plan| {
+                infile_plan.outfiles.iter()
+                    .filter_map(|outfile| outfile.error.as_deref())
+                    .map(|error| format!("{}: {error}", infile_plan.infile.display()))
+            })
+            .collect::<Vec<_>>();
+        if !unplanned.is_empty() {
+            bail!(
+                "{} outputs couldn't be planned:\n  {}",
+                unplanned.len(),
+                unplanned.join("\n  "),
+            );
+        }
+
+        // Each expected output and the input and format it's made from.
+        let expected: BTreeMap<&Path, (&Path, Format)> = plan.outputs.iter()
+            .flat_map(|infile_plan| {
+                infile_plan.outfiles.iter().map(|outfile| {
+                    (outfile.path.as_path(), (infile_plan.infile.as_path(), outfile.format))
+                })
+            })
+            .collect();
+        let is_kept_beside_expected = |path: &Path| -> bool {
+            let Some(original) = keep_both_original(path) else {
+                return false;
+            };
+            let Some((infile, format)) = expected.get(original.as_path()) else {
+                return false;
+            };
+            plan.manifest.outputs.get(path).is_some_and(|entry| {
+                entry.in_path == *infile && entry.format == *format
+            })
+        };
+        let inputs: BTreeSet<&Path> = plan.outputs.iter()
+            .map(|infile_plan| infile_plan.infile.as_path())
+            .collect();
+        let exts: BTreeSet<&str> = config.formats.iter()
+            .map(|format_config| format_config.format.codec.ext())
+            .collect();
+
+        let mut stale = vec![];
+
+        if !config.out_root_dir.exists() {
+            return Ok(stale);
+        }
+
+        for entry in WalkDir::new(&config.out_root_dir) {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = entry.path();
+            let ext = path.extension().and_then(OsStr::to_str);
+            let is_output_ext = ext.map(|ext| exts.contains(ext)).unwrap_or(false);
+
+            if !is_output_ext
+                || expected.contains_key(path)
+                || inputs.contains(path)
+                || is_kept_beside_expected(path)
+            {
+                continue;
+            }
+
+            stale.push(path.to_owned());
+        }
+
+        Ok(stale)
+    }
+
+    /// Delete stale outputs, any directories left empty,
+    /// and their manifest entries.
+    pub fn remove_outputs(config: &Config, paths: &[PathBuf]) -> AnyResult<()> {
+        for path in paths {
+            fs::remove_file(path)
+                .context(format!("removing {}", path.display()))?;
+
+            // Remove now-empty directories up to the output root.
+            let mut dir = path.parent();
+            while let Some(d) = dir {
+                if d == config.out_root_dir || !d.starts_with(&config.out_root_dir) {
+                    break;
+                }
+                if fs::remove_dir(d).is_err() {
+                    // Not empty.
+                    break;
+                }
+                dir = d.parent();
+            }
+        }
+
+        let manifest_path = Manifest::path(&config.out_root_dir);
+        let mut manifest = Manifest::load(&manifest_path)?;
+        let manifest_len = manifest.outputs.len();
+        for path in paths {
+            manifest.outputs.remove(path);
+        }
+        if manifest.outputs.len() != manifest_len {
+            manifest.save(&manifest_path)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -153,33 +1118,111 @@ pub mod exec {
 
     use rmx::prelude::*;
     use rmx::rayon::{self, prelude::*};
+    use rmx::serde::{Serialize, Serializer};
 
     use rmx::walkdir::{self, WalkDir, DirEntry};
     use std::sync::mpsc::{SyncSender, Receiver, sync_channel};
-    use std::sync::Arc;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, Condvar};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::num::NonZeroUsize;
     use std::thread;
     use std::path::{PathBuf, Path};
+    use std::time::{Duration, Instant, SystemTime};
     use std::fs;
 
     pub enum Request {
         Cancel,
     }
 
+    /// Serializes as an object with an `event` field naming the variant.
+    #[derive(Serialize)]
+    #[serde(tag = "event", rename_all = "snake_case")]
     pub enum Response {
+        /// Conversion of an input file has begun.
+        FileStarted {
+            in_path: PathBuf,
+            total_frames: Option<u64>,
+        },
+        /// Frames of an input file read so far.
+        ///
+        /// Sent periodically, not for every buffer.
+        Progress {
+            in_path: PathBuf,
+            frames: u64,
+            total_frames: Option<u64>,
+        },
+        /// All outputs of an input file are done,
+        /// and their results have been sent.
+        FileFinished {
+            in_path: PathBuf,
+        },
+        #[serde(rename = "result")]
         NextResult(ConvertResult),
         Done,
         Cancelled,
     }
 
+    const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[derive(Serialize)]
     #[derive(Debug)]
     pub struct ConvertResult {
         pub in_path: PathBuf,
         pub out_path: PathBuf,
         pub format: Format,
+        pub action: OutFileAction,
+        /// Where silence was trimmed, if the output has a `TrimSilence` stage.
+        pub silence_trim: Option<SilenceTrim>,
+        /// Levels of the converted audio, before bit depth conversion.
+        pub stats: Option<OutputStats>,
+        #[serde(serialize_with = "serialize_error")]
         pub error: AnyResult<()>,
     }
 
+    /// The error of outputs left unfinished because conversion was cancelled.
+    #[derive(Debug)]
+    pub struct Cancelled;
+
+    impl std::fmt::Display for Cancelled {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "cancelled")
+        }
+    }
+
+    impl std::error::Error for Cancelled { }
+
+    impl ConvertResult {
+        pub fn is_cancelled(&self) -> bool {
+            match &self.error {
+                Err(e) => e.is::<Cancelled>(),
+                Ok(()) => false,
+            }
+        }
+    }
+
+    /// Errors serialize as `null` on success,
+    /// or the message and each of its causes.
+    fn serialize_error<S: Serializer>(
+        error: &AnyResult<()>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct SerError {
+            message: String,
+            causes: Vec<String>,
+        }
+
+        match error {
+            Ok(()) => serializer.serialize_none(),
+            Err(e) => {
+                serializer.serialize_some(&SerError {
+                    message: e.to_string(),
+                    causes: e.chain().skip(1).map(|e| e.to_string()).collect(),
+                })
+            }
+        }
+    }
+
     pub fn spawn(plan: Plan) -> (
         SyncSender<Request>,
         Receiver<Response>,
@@ -215,10 +1258,67 @@ pub mod exec {
             }
         });
 
-        plan.outputs.par_iter().for_each(|infile_plan| {
-            convert_file(infile_plan, &tx, &cancel);
+        let manifest_entries = Mutex::new(Vec::new());
+
+        // Files are converted on their own threads, not rayon's,
+        // so waiting on the memory budget can't starve
+        // the rayon tasks of the files that would free it.
+        let jobs = plan.limits.jobs
+            .or_else(|| thread::available_parallelism().ok())
+            .map(NonZeroUsize::get)
+            .unwrap_or(1);
+        let block_frames = plan.limits.block_frames();
+        let budget = plan.limits.max_buffer_memory.map(MemoryBudget::new);
+        let next = AtomicUsize::new(0);
+
+        // The outputs of each file are converted in parallel,
+        // on a pool of `jobs` threads rather than rayon's global pool,
+        // so `jobs` bounds the threads converting at once.
+        let pool = match rayon::ThreadPoolBuilder::new().num_threads(jobs).build() {
+            Ok(pool) => Some(pool),
+            Err(e) => {
+                warn!("error starting {jobs} conversion threads, using the global pool: {e}");
+                None
+            }
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..jobs.min(plan.outputs.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(infile_plan) = plan.outputs.get(index) else {
+                            break;
+                        };
+                        let _reservation = budget.as_ref().map(|budget| {
+                            budget.reserve(buffer_estimate(infile_plan, block_frames))
+                        });
+                        let convert = || convert_file(
+                            infile_plan,
+                            &plan.manifest,
+                            plan.verify,
+                            block_frames,
+                            &manifest_entries,
+                            &tx,
+                            &cancel,
+                        );
+                        match &pool {
+                            Some(pool) => pool.install(convert),
+                            None => convert(),
+                        }
+                    }
+                });
+            }
         });
 
+        if let Some(manifest_path) = &plan.manifest_path {
+            let mut manifest = plan.manifest;
+            manifest.outputs.extend(manifest_entries.into_inner().expect("lock"));
+            if let Err(e) = manifest.save(manifest_path) {
+                error!("error saving manifest {}: {e}", manifest_path.display());
+            }
+        }
+
         if !cancel.load(Ordering::SeqCst) {
             let _ = tx.send(Response::Done);
         } else {
@@ -226,13 +1326,81 @@ pub mod exec {
         }
     }
 
+    /// A rough upper bound on the buffer memory converting a file takes.
+    ///
+    /// Assumes stereo `F32` blocks, three for the decoded,
+    /// `F32` and DSP buffers of the input, and for each output
+    /// the resampled and bit depth converted buffers
+    /// and the encoder's own.
+    fn buffer_estimate(plan: &InfilePlan, block_frames: usize) -> u64 {
+        let block_bytes = block_frames as u64 * 2 * 4;
+        let per_input = 3 * block_bytes;
+        // Resampling can quadruple a buffer.
+        let per_output = 4 * block_bytes + block_bytes + 2 * block_bytes;
+
+        let outputs = plan.outfiles.iter()
+            .filter(|outfile| outfile.error.is_none())
+            .filter(|outfile| !matches!(outfile.action, OutFileAction::Skip(_)))
+            .count() as u64;
+        per_input + outputs * per_output
+    }
+
+    /// Bytes of buffer memory shared by the files being converted.
+    struct MemoryBudget {
+        limit: u64,
+        used: Mutex<u64>,
+        freed: Condvar,
+    }
+
+    struct Reservation<'budget> {
+        budget: &'budget MemoryBudget,
+        bytes: u64,
+    }
+
+    impl MemoryBudget {
+        fn new(limit: u64) -> MemoryBudget {
+            MemoryBudget {
+                limit,
+                used: Mutex::new(0),
+                freed: Condvar::new(),
+            }
+        }
+
+        /// Wait until `bytes` fit in the budget,
+        /// or nothing else is reserved.
+        fn reserve(&self, bytes: u64) -> Reservation<'_> {
+            let used = self.used.lock().expect("lock");
+            let mut used = self.freed.wait_while(used, |used| {
+                *used > 0 && *used + bytes > self.limit
+            }).expect("lock");
+            *used += bytes;
+            Reservation { budget: self, bytes }
+        }
+    }
+
+    impl Drop for Reservation<'_> {
+        fn drop(&mut self) {
+            let mut used = self.budget.used.lock().expect("lock");
+            *used -= self.bytes;
+            self.budget.freed.notify_all();
+        }
+    }
+
     fn convert_file(
         plan: &InfilePlan,
+        manifest: &Manifest,
+        verify: bool,
+        block_frames: usize,
+        manifest_entries: &Mutex<Vec<(PathBuf, ManifestEntry)>>,
         tx: &SyncSender<Response>,
         cancel: &AtomicBool,
     ) {
         let plan = FilePlan::new(
             plan,
+            manifest,
+            verify,
+            block_frames,
+            manifest_entries,
             tx,
             cancel,
         );
@@ -244,67 +1412,173 @@ pub mod exec {
     use rmx::rand::Rng;
     use crate::types::{Format, SampleRate, BitDepth};
     use std::collections::BTreeMap;
-    use crate::io::{PcmReader, PcmWriter, PanicPcmWriter, Buf, Props};
+    use crate::io::{PcmReader, PcmWriter, PanicPcmWriter, PcmHasher, Buf, Props};
     use crate::samplerate::SampleRateConverter;
     use crate::bitdepth::BitDepthConverter;
+    use crate::dsp::{DspStage, DspChain, DspReport, SilenceTrim};
+    use crate::stats::{SignalStats, OutputStats};
     use crate::codecs;
-    use super::OutFile;
+    use super::manifest::{self, Manifest, ManifestEntry};
+    use super::{OutFile, OutFileAction};
 
     type FormatPlan =
-        BTreeMap<
-            SampleRate,
-            BTreeMap<BitDepth, Vec<OutFile>>
-        >;
+        Vec<(
+            Vec<DspStage>,
+            BTreeMap<
+                SampleRate,
+                BTreeMap<BitDepth, Vec<OutFile>>
+            >,
+        )>;
     type ConverterPlan =
+        Vec<(
+            DspChain,
+            SampleRatePlan,
+        )>;
+    type SampleRatePlan =
         BTreeMap<
             SampleRate, (
                 SampleRateConverter,
-                BTreeMap<
-                    BitDepth, (
-                        BitDepthConverter,
-                        Vec<Option<OutFileWriter>>
-                    )
-                >
+                SignalStats,
+                BitDepthPlan,
+            )
+        >;
+    type BitDepthPlan =
+        BTreeMap<
+            BitDepth, (
+                BitDepthConverter,
+                Vec<Option<OutFileWriter>>
             )
         >;
     struct FilePlan<'up> {
         cancel: &'up AtomicBool,
         tx: &'up SyncSender<Response>,
         infile: &'up Path,
-        sample_rates: FormatPlan,
+        input_hash: Option<String>,
+        input_stamp: Option<(u64, SystemTime)>,
+        dsp_chains: FormatPlan,
+        skipped: Vec<OutFile>,
+        /// Outputs that failed to plan.
+        unplanned: Vec<OutFile>,
+        verify: bool,
+        block_frames: usize,
+        manifest_entries: &'up Mutex<Vec<(PathBuf, ManifestEntry)>>,
+    }
+
+    /// What is known about an output once all of it is written.
+    struct OutputReport {
+        silence_trim: Option<SilenceTrim>,
+        stats: OutputStats,
     }
 
     struct OutFileWriter {
-        path: PathBuf,
+        outfile: OutFile,
         tmp_path: PathBuf,
-        format: Format,
+        props: Props,
+        settings_hash: String,
         writer: Box<dyn PcmWriter>,
+        // Hash of everything written, for verification.
+        hasher: Option<PcmHasher>,
     }
 
     impl<'up> FilePlan<'up> {
         fn new<'up_>(
             plan: &'up_ InfilePlan,
+            manifest: &Manifest,
+            verify: bool,
+            block_frames: usize,
+            manifest_entries: &'up_ Mutex<Vec<(PathBuf, ManifestEntry)>>,
             tx: &'up_ SyncSender<Response>,
             cancel: &'up_ AtomicBool,
         ) -> FilePlan<'up_> {
-            let mut sample_rates: BTreeMap<SampleRate, BTreeMap<BitDepth, Vec<OutFile>>> = BTreeMap::new();
+            let mut dsp_chains: FormatPlan = Vec::new();
+            let mut skipped = Vec::new();
+            let mut unplanned = Vec::new();
 
             for outfile in &plan.outfiles {
+                if outfile.error.is_some() {
+                    unplanned.push(outfile.clone());
+                    continue;
+                }
+
+                if let OutFileAction::Skip(_) = outfile.action {
+                    skipped.push(outfile.clone());
+                    continue;
+                }
+
+                // Outputs with identical DSP chains share one chain instance.
+                let existing = dsp_chains.iter().position(|(dsp, _)| dsp == &outfile.dsp);
+                let index = match existing {
+                    Some(index) => index,
+                    None => {
+                        dsp_chains.push((outfile.dsp.clone(), BTreeMap::new()));
+                        dsp_chains.len() - 1
+                    }
+                };
+                let sample_rates = &mut dsp_chains[index].1;
                 let mut bit_depths = sample_rates.entry(outfile.format.sample_rate).or_default();
                 let mut out_files = bit_depths.entry(outfile.format.bit_depth).or_default();
                 out_files.push(outfile.clone());
             }
 
+            let input_stamp = match manifest::file_stamp(&plan.infile) {
+                Ok(stamp) => Some(stamp),
+                Err(e) => {
+                    warn!("error reading metadata of {}: {e}", plan.infile.display());
+                    None
+                }
+            };
+
+            // The planner only hashes inputs in `Incremental::Hash` mode,
+            // but the manifest records hashes of everything converted.
+            let input_hash = match &plan.input_hash {
+                Some(input_hash) => Some(input_hash.clone()),
+                None if !dsp_chains.is_empty() => {
+                    let out_paths = plan.outfiles.iter().map(|outfile| outfile.path.as_path());
+                    match manifest.input_hash(&plan.infile, input_stamp, out_paths) {
+                        Ok(input_hash) => Some(input_hash),
+                        Err(e) => {
+                            warn!("error hashing {}: {e}", plan.infile.display());
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
+
             FilePlan {
                 cancel,
                 tx,
                 infile: &plan.infile,
-                sample_rates,
+                input_hash,
+                input_stamp,
+                dsp_chains,
+                skipped,
+                unplanned,
+                verify,
+                block_frames,
+                manifest_entries,
             }
         }
 
         fn converter_plan(&self, source_props: &Props) -> ConverterPlan {
-            self.sample_rates.iter().map(|args| {
+            self.dsp_chains.iter().map(|(dsp, sample_rates)| {
+                (
+                    DspChain::new(
+                        dsp,
+                        source_props.channels,
+                        source_props.format.sample_rate,
+                    ),
+                    self.sample_rate_plan(source_props, sample_rates),
+                )
+            }).collect()
+        }
+
+        fn sample_rate_plan(
+            &self,
+            source_props: &Props,
+            sample_rates: &BTreeMap<SampleRate, BTreeMap<BitDepth, Vec<OutFile>>>,
+        ) -> SampleRatePlan {
+            sample_rates.iter().map(|args| {
                 let (
                     sample_rate,
                     bit_depths,
@@ -319,22 +1593,35 @@ pub mod exec {
                     let writers = outfiles.iter().map(|outfile| {
                         let out_dir = outfile.path.parent();
                         // fixme only call create_dir_all once per directory
-                        // fixme error handling
-                        if let Some(out_dir) = out_dir {
-                            match std::fs::create_dir_all(&out_dir) {
-                                Ok(_) => { }
-                                Err(e) => todo!("{e}"),
-                            }
+                        if let Some(out_dir) = out_dir
+                            && let Err(e) = fs::create_dir_all(out_dir)
+                        {
+                            let e = AnyError::from(e)
+                                .context(format!("creating directory {}", out_dir.display()));
+                            self.fail_outfile(outfile, e);
+                            return None;
                         }
                         let tmp_path = tmp_path(&outfile.path);
-                        Some(OutFileWriter {
-                            path: outfile.path.clone(),
-                            tmp_path: tmp_path.clone(),
+                        let props = Props {
+                            channels: source_props.channels,
                             format: outfile.format,
-                            writer: codecs::writer(&tmp_path, Props {
-                                channels: source_props.channels,
-                                format: outfile.format,
-                            }),
+                        };
+                        let writer = match codecs::writer(&tmp_path, props, &outfile.encoder) {
+                            Ok(writer) => writer,
+                            Err(e) => {
+                                // The encoder may have created the file before failing.
+                                let _ = fs::remove_file(&tmp_path);
+                                self.fail_outfile(outfile, e.context("error creating output"));
+                                return None;
+                            }
+                        };
+                        Some(OutFileWriter {
+                            outfile: outfile.clone(),
+                            tmp_path,
+                            props,
+                            settings_hash: outfile.settings_hash(),
+                            writer,
+                            hasher: self.verify.then(PcmHasher::new),
                         })
                     }).collect();
 
@@ -359,6 +1646,7 @@ pub mod exec {
                             *sample_rate,
                             source_props.channels,
                         ),
+                        SignalStats::new(source_props.channels),
                         bit_depths,
                     ),
                 )
@@ -367,35 +1655,96 @@ pub mod exec {
 
         fn prepare(&self) -> AnyResult<(
             Box<dyn PcmReader>,
+            Props,
             ConverterPlan,
             BitDepthConverter,
         )> {
             let mut reader = codecs::reader(&self.infile)?;
+            reader.set_block_frames(self.block_frames);
             let source_props = reader.props()?;
-            let mut sample_rates = self.converter_plan(&source_props);
+            let mut dsp_chains = self.converter_plan(&source_props);
             let mut f32_converter = BitDepthConverter::new(
                 source_props.format.bit_depth,
                 BitDepth::F32,
                 source_props.format.bit_depth,
             );
 
-            Ok((reader, sample_rates, f32_converter))
+            Ok((reader, source_props, dsp_chains, f32_converter))
         }
             
 
         fn run(&self) {
+            for outfile in &self.skipped {
+                self.tx.send(Response::NextResult(
+                    ConvertResult {
+                        in_path: self.infile.to_owned(),
+                        out_path: outfile.path.clone(),
+                        format: outfile.format,
+                        action: outfile.action,
+                        silence_trim: None,
+                        stats: None,
+                        error: Ok(()),
+                    }
+                ));
+            }
+
+            // Not recorded in the manifest, as their paths may only be guesses.
+            for outfile in &self.unplanned {
+                let _ = self.tx.send(Response::NextResult(
+                    ConvertResult {
+                        in_path: self.infile.to_owned(),
+                        out_path: outfile.path.clone(),
+                        format: outfile.format,
+                        action: outfile.action,
+                        silence_trim: None,
+                        stats: None,
+                        error: Err(anyhow!("{}", outfile.error.as_deref().unwrap_or_default())),
+                    }
+                ));
+            }
+
+            if self.dsp_chains.is_empty() {
+                // Everything skipped.
+                return;
+            }
+
             let (
                 mut reader,
-                mut sample_rates,
+                source_props,
+                mut dsp_chains,
                 mut f32_converter,
             ) = match self.prepare() {
                 Ok(preps) => preps,
                 Err(e) => {
-                    todo!("{e}");
+                    let e = e.context("error opening input");
+                    for (_, sample_rates) in &self.dsp_chains {
+                        let outfiles = sample_rates.values()
+                            .flat_map(|bit_depths| bit_depths.values())
+                            .flatten();
+                        for outfile in outfiles {
+                            self.fail_outfile(outfile, anyhow!("{e:#}"));
+                        }
+                    }
+                    return;
+                }
+            };
+            let channels = source_props.channels as usize;
+            let total_frames = match reader.total_frames() {
+                Ok(total_frames) => total_frames,
+                Err(e) => {
+                    warn!("error reading length of {}: {e}", self.infile.display());
+                    None
                 }
             };
+            let _ = self.tx.send(Response::FileStarted {
+                in_path: self.infile.to_owned(),
+                total_frames,
+            });
+
             let mut buf = Buf::Uninit;
             let mut read_error = Ok(());
+            let mut frames = 0;
+            let mut last_progress = Instant::now();
 
             loop {
                 if self.cancel.load(Ordering::SeqCst) {
@@ -410,267 +1759,794 @@ pub mod exec {
                     }
                 }
 
+                frames += (buf.len() / channels) as u64;
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    last_progress = Instant::now();
+                    let _ = self.tx.send(Response::Progress {
+                        in_path: self.infile.to_owned(),
+                        frames,
+                        total_frames,
+                    });
+                }
+
                 let buf = f32_converter.convert(&buf);
 
                 // At this point `buf` either has data,
                 // or is empty if EOF. Even if EOF
-                // we may need to keep doing sample rate conversion
-                // to pick up the any remaining buffers in the SRC.
+                // we may need to keep doing DSP and sample rate conversion
+                // to pick up the any remaining buffers in the DSP chains and SRC.
+                let eof = buf.is_empty();
 
-                let keep_going = sample_rates.par_iter_mut().try_for_each(|args| {
+                let keep_going = dsp_chains.par_iter_mut().try_for_each(|args| {
                     let (
-                        sample_rate,
-                        (
-                            sample_rate_converter,
-                            bit_depths,
-                        ),
+                        dsp_chain,
+                        sample_rates,
                     ) = args;
 
-                    let buf = if !buf.is_empty() {
-                        let buf = sample_rate_converter.convert(&buf);
-                        if buf.is_empty() {
-                            // The SRC didn't produce any samples,
-                            // which might happen with short input and
-                            // reducing the sample rate.
-                            return Some(());
-                        } else {
-                            buf
-                        }
-                    } else {
-                        sample_rate_converter.finalize()
+                    let buf = dsp_chain.process(buf);
+
+                    // The DSP chain may hold back or drop samples,
+                    // so an empty buffer here doesn't mean EOF.
+                    if !buf.is_empty() {
+                        self.write_sample_rates(buf, None, sample_rates)?;
+                    }
+
+                    if eof {
+                        let (buf, report) = dsp_chain.finish();
+                        self.write_sample_rates(buf, Some(&report), sample_rates)?;
+                    }
+
+                    Some(())
+                });
+
+                if keep_going.is_none() {
+                    break;
+                }
+
+                if eof {
+                    break;
+                }
+            }
+
+            self.do_cleanups(dsp_chains, read_error);
+
+            let _ = self.tx.send(Response::FileFinished {
+                in_path: self.infile.to_owned(),
+            });
+        }
+
+        /// Write to all outputs of one DSP chain.
+        /// At EOF `finished` is the chain's report, and the outputs are finalized.
+        fn write_sample_rates(
+            &self,
+            buf: &Buf,
+            finished: Option<&DspReport>,
+            sample_rates: &mut SampleRatePlan,
+        ) -> Option<()> {
+            sample_rates.par_iter_mut().try_for_each(|args| {
+                let (
+                    sample_rate,
+                    (
+                        sample_rate_converter,
+                        stats,
+                        bit_depths,
+                    ),
+                ) = args;
+
+                if !buf.is_empty() {
+                    let buf = sample_rate_converter.convert(buf);
+                    // The SRC may not produce any samples,
+                    // which might happen with short input and
+                    // reducing the sample rate.
+                    if !buf.is_empty() {
+                        stats.update(buf);
+                        self.write_bit_depths(buf, None, bit_depths)?;
+                    }
+                }
+
+                if let Some(dsp_report) = finished {
+                    let buf = sample_rate_converter.finalize();
+                    stats.update(buf);
+                    let report = OutputReport {
+                        silence_trim: dsp_report.silence_trim,
+                        stats: stats.summary(*sample_rate),
                     };
+                    self.write_bit_depths(buf, Some(&report), bit_depths)?;
+                }
 
-                    self.report_overs(&buf, args.0);
+                Some(())
+            })
+        }
 
-                    bit_depths.par_iter_mut().try_for_each(|args| {
-                        let (
-                            bit_depth,
-                            (
-                                bit_depth_converter,
-                                writers,
-                            ),
-                        ) = args;
+        fn write_bit_depths(
+            &self,
+            buf: &Buf,
+            finished: Option<&OutputReport>,
+            bit_depths: &mut BitDepthPlan,
+        ) -> Option<()> {
+            bit_depths.par_iter_mut().try_for_each(|args| {
+                let (
+                    bit_depth,
+                    (
+                        bit_depth_converter,
+                        writers,
+                    ),
+                ) = args;
 
-                        let buf = bit_depth_converter.convert(buf);
+                let buf = bit_depth_converter.convert(buf);
 
-                        writers.par_iter_mut().try_for_each(|writer_ref| {
+                writers.par_iter_mut().try_for_each(|writer_ref| {
 
-                            // todo: is it worth doing this check here?
-                            // we already did it in the outer loop.
-                            if self.cancel.load(Ordering::SeqCst) {
-                                return None;
+                    // todo: is it worth doing this check here?
+                    // we already did it in the outer loop.
+                    if self.cancel.load(Ordering::SeqCst) {
+                        return None;
+                    }
+
+                    // If there is any error writing the file we will
+                    // handle it now, and set the writer to `None` for
+                    // future iterations.
+                    let writer = std::mem::replace(writer_ref, None);
+
+                    // If the writer is `None` then there was an error
+                    // previously, or the file is already finished.
+                    if let Some(mut writer) = writer {
+                        if !buf.is_empty() {
+                            let res = writer.writer.write(buf);
+                            if let Err(e) = res {
+                                self.handle_error(writer, e);
+                                return Some(());
                             }
+                            if let Some(hasher) = &mut writer.hasher {
+                                hasher.update(buf);
+                            }
+                        }
 
-                            // If there is any error writing the file we will
-                            // handle it now, and set the writer to `None` for
-                            // future iterations.
-                            let writer = std::mem::replace(writer_ref, None);
-
-                            // If the writer is `None` then there was an error
-                            // previously.
-                            if let Some(mut writer) = writer {
-                                let mut handle_error = |writer: OutFileWriter, e| {
-                                    // Drop the writer so it closes any handles.
-                                    // This might matter on windows.
-                                    drop(writer.writer);
-                                    let res = fs::remove_file(&writer.tmp_path);
-                                    if let Err(e) = res {
-                                        error!("error removing temp file while handling error");
-                                    }
-                                    self.tx.send(Response::NextResult(
-                                        ConvertResult {
-                                            in_path: self.infile.to_owned(),
-                                            out_path: writer.path,
-                                            format: writer.format,
-                                            error: Err(e),
-                                        }
-                                    ));
-                                };
-                                if !buf.is_empty() {
-                                    let res = writer.writer.write(buf);
-                                    if let Err(e) = res {
-                                        handle_error(writer, e);
-                                    } else {
-                                        *writer_ref = Some(writer);
-                                    }
-                                } else {
-                                    let res = writer.writer.finalize();
-                                    if let Err(e) = res {
-                                        handle_error(writer, e);
-                                    } else {
-                                        let format = writer.format;
-                                        // Drop the writer so it closes any handles.
-                                        // This might matter on windows.
-                                        drop(writer.writer);
-                                        let res = fs::rename(&writer.tmp_path, &writer.path);
-                                        if let Err(e) = res {
-                                            writer.writer = Box::new(PanicPcmWriter);
-                                            handle_error(writer, e.into());
-                                        } else {
-                                            // success!
-                                            self.tx.send(Response::NextResult(
-                                                ConvertResult {
-                                                    in_path: self.infile.to_owned(),
-                                                    out_path: writer.path,
-                                                    format: writer.format,
-                                                    error: Ok(()),
-                                                }
-                                            ));
-                                        }
-                                    }
-                                }
+                        match finished {
+                            None => {
+                                *writer_ref = Some(writer);
                             }
+                            Some(report) => {
+                                self.finish_writer(writer, report);
+                            }
+                        }
+                    }
 
-                            Some(())
-                        })
-                    })
-                });
+                    Some(())
+                })
+            })
+        }
 
-                if keep_going.is_none() {
-                    break;
+        fn finish_writer(&self, mut writer: OutFileWriter, report: &OutputReport) {
+            let res = writer.writer.finalize();
+            if let Err(e) = res {
+                self.handle_error(writer, e);
+            } else {
+                // Drop the writer so it closes any handles.
+                // This might matter on windows.
+                drop(writer.writer);
+                writer.writer = Box::new(PanicPcmWriter);
+                if let Some(hasher) = &writer.hasher {
+                    let res = verify_output(&writer.tmp_path, writer.props, hasher);
+                    if let Err(e) = res {
+                        self.handle_error(writer, e.context("verification failed"));
+                        return;
+                    }
                 }
-
-                if buf.is_empty() {
-                    break;
+                let res = fs::rename(&writer.tmp_path, &writer.outfile.path);
+                if let Err(e) = res {
+                    self.handle_error(writer, e.into());
+                } else {
+                    // success!
+                    self.record(&writer.outfile, &writer.settings_hash, Some(report.stats), None);
+                    self.tx.send(Response::NextResult(
+                        ConvertResult {
+                            in_path: self.infile.to_owned(),
+                            out_path: writer.outfile.path,
+                            format: writer.props.format,
+                            action: writer.outfile.action,
+                            silence_trim: report.silence_trim,
+                            stats: Some(report.stats),
+                            error: Ok(()),
+                        }
+                    ));
                 }
             }
+        }
+
+        fn handle_error(&self, writer: OutFileWriter, e: AnyError) {
+            // Drop the writer so it closes any handles.
+            // This might matter on windows.
+            drop(writer.writer);
+            let res = fs::remove_file(&writer.tmp_path);
+            if let Err(e) = res {
+                error!("error removing temp file while handling error");
+            }
+            self.record(&writer.outfile, &writer.settings_hash, None, Some(&e));
+            self.tx.send(Response::NextResult(
+                ConvertResult {
+                    in_path: self.infile.to_owned(),
+                    out_path: writer.outfile.path,
+                    format: writer.props.format,
+                    action: writer.outfile.action,
+                    silence_trim: None,
+                    stats: None,
+                    error: Err(e),
+                }
+            ));
+        }
+
+        /// Report an output that failed before it had a writer.
+        fn fail_outfile(&self, outfile: &OutFile, e: AnyError) {
+            self.record(outfile, &outfile.settings_hash(), None, Some(&e));
+            let _ = self.tx.send(Response::NextResult(
+                ConvertResult {
+                    in_path: self.infile.to_owned(),
+                    out_path: outfile.path.clone(),
+                    format: outfile.format,
+                    action: outfile.action,
+                    silence_trim: None,
+                    stats: None,
+                    error: Err(e),
+                }
+            ));
+        }
 
-            self.do_cleanups(sample_rates, read_error);
+        fn record(
+            &self,
+            outfile: &OutFile,
+            settings_hash: &str,
+            stats: Option<OutputStats>,
+            error: Option<&AnyError>,
+        ) {
+            let entry = ManifestEntry {
+                in_path: self.infile.to_owned(),
+                input_hash: self.input_hash.clone(),
+                input_size: self.input_stamp.map(|(size, _)| size),
+                input_mtime: self.input_stamp.map(|(_, mtime)| mtime),
+                format: outfile.format,
+                dsp: outfile.dsp.clone(),
+                settings_hash: settings_hash.to_string(),
+                tool_version: S(manifest::TOOL_VERSION),
+                stats,
+                success: error.is_none(),
+                error: error.map(|e| format!("{e:#}")),
+            };
+            let mut manifest_entries = self.manifest_entries.lock().expect("lock");
+            manifest_entries.push((outfile.path.clone(), entry));
         }
 
         fn do_cleanups(
             &self,
-            sample_rates: ConverterPlan,
+            dsp_chains: ConverterPlan,
             read_error: Result<(), Arc<rmx::anyhow::Error>>,
         ) {
             // Do cleanups and send cancellation / file read errors.
-            for (_, (_, bit_depths)) in sample_rates.into_iter() {
-                for (_, (_, writers)) in bit_depths.into_iter() {
-                    // Any writers that are `None` have been completed,
-                    // either written fully, or errored;
-                    // and don't need to be cleaned up on cancellation or read error.
-                    // `filter_map` on the identity function will remove `None`s.
-                    let remaining_writers = writers.into_iter().filter_map(std::convert::identity);
-                    for writer in remaining_writers {
-                        // Conversion was cancelled or there was
-                        // an error reading the infile.
-
-                        // Drop the writer so it closes any handles.
-                        // This might matter on windows.
-                        drop(writer.writer);
-
-                        let res = fs::remove_file(&writer.tmp_path);
-                        if let Err(e) = res {
-                            error!("error removing temp file while handling error: {e}");
-                        }
+            let all_writers = dsp_chains.into_iter()
+                .flat_map(|(_, sample_rates)| sample_rates.into_values())
+                .flat_map(|(_, _, bit_depths)| bit_depths.into_values())
+                .flat_map(|(_, writers)| writers);
+            // Any writers that are `None` have been completed,
+            // either written fully, or errored;
+            // and don't need to be cleaned up on cancellation or read error.
+            // `filter_map` on the identity function will remove `None`s.
+            let remaining_writers = all_writers.filter_map(std::convert::identity);
+            for writer in remaining_writers {
+                // Conversion was cancelled or there was
+                // an error reading the infile.
+
+                // Drop the writer so it closes any handles.
+                // This might matter on windows.
+                drop(writer.writer);
+
+                let res = fs::remove_file(&writer.tmp_path);
+                if let Err(e) = res {
+                    error!("error removing temp file while handling error: {e}");
+                }
 
-                        match read_error.as_ref() {
-                            Ok(()) => {
-                                self.tx.send(Response::NextResult(
-                                    ConvertResult {
-                                        in_path: self.infile.to_owned(),
-                                        out_path: writer.path,
-                                        format: writer.format,
-                                        error: Err(anyhow!("cancelled")),
-                                    }
-                                ));
+                match read_error.as_ref() {
+                    Ok(()) => {
+                        self.tx.send(Response::NextResult(
+                            ConvertResult {
+                                in_path: self.infile.to_owned(),
+                                out_path: writer.outfile.path,
+                                format: writer.props.format,
+                                action: writer.outfile.action,
+                                silence_trim: None,
+                                stats: None,
+                                error: Err(Cancelled.into()),
                             }
-                            Err(e) => {
-                                self.tx.send(Response::NextResult(
-                                    ConvertResult {
-                                        in_path: self.infile.to_owned(),
-                                        out_path: writer.path,
-                                        format: writer.format,
-                                        // fixme: don't stringify this error
-                                        error: Err(anyhow!("{}", e).context("file read error")),
-                                    }
-                                ));
+                        ));
+                    }
+                    Err(e) => {
+                        let e = anyhow!("{}", e).context("file read error");
+                        self.record(&writer.outfile, &writer.settings_hash, None, Some(&e));
+                        self.tx.send(Response::NextResult(
+                            ConvertResult {
+                                in_path: self.infile.to_owned(),
+                                out_path: writer.outfile.path,
+                                format: writer.props.format,
+                                action: writer.outfile.action,
+                                silence_trim: None,
+                                stats: None,
+                                // fixme: don't stringify this error
+                                error: Err(e),
                             }
-                        }
+                        ));
                     }
                 }
             }
+        }
+    }
 
+    /// Read back a finished output and check it matches what was written.
+    pub fn verify_output(
+        path: &Path,
+        props: Props,
+        written: &PcmHasher,
+    ) -> AnyResult<()> {
+        let mut reader = codecs::codec_reader(path, props.format.codec);
+        let read_props = reader.props()?;
+        if read_props != props {
+            bail!("wrote {props:?} but read back {read_props:?}");
         }
 
-        fn report_overs(&self, buf: &Buf, sample_rate: &SampleRate) {
-            // todo
+        // Always decode the whole file,
+        // but only lossless codecs can be compared exactly.
+        let mut hasher = PcmHasher::new();
+        let mut buf = Buf::Uninit;
+        loop {
+            reader.read(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.update(&buf);
+        }
+
+        if props.format.codec.is_lossless() {
+            let channels = props.channels as u64;
+            let written_frames = written.samples() / channels;
+            let read_frames = hasher.samples() / channels;
+            if written_frames != read_frames {
+                bail!("wrote {written_frames} frames but read back {read_frames}");
+            }
+            if written.hash() != hasher.hash() {
+                bail!("samples read back differ from samples written");
+            }
         }
+
+        Ok(())
     }
 
     fn tmp_path(path: &Path) -> PathBuf {
         let mut tmp_path = path.to_owned();
-        let ext = path.extension().expect("extension");
-        let ext = ext.to_string_lossy().to_string();
+        let mut ext = path.extension().expect("extension").to_owned();
         let random: u16 = rmx::rand::rng().random();
-        let ext = format!("{ext}.{random:04X}.tmp");
+        ext.push(format!(".{random:04X}.tmp"));
         tmp_path.set_extension(ext);
         tmp_path
     }
 }
 
 use crate::types::{Format, Codec};
-use self::config::Config;
+use crate::dsp::DspStage;
+use crate::codecs::{self, EncoderSettings};
+use crate::info;
+use self::config::{Config, InputRoot};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use rmx::prelude::*;
 use rmx::tera::{Tera, Context as TeraContext};
+use rmx::tera::ast::{Node, Expr, ExprVal, FunctionCall};
 use rmx::serde::Serialize;
 
+#[derive(Serialize)]
 #[derive(Clone)]
 #[derive(Debug)]
 pub struct OutFile {
     path: PathBuf,
     format: Format,
+    dsp: Vec<DspStage>,
+    encoder: EncoderSettings,
+    action: OutFileAction,
+    /// Why the output can't be made, as found when planning.
+    /// If its path couldn't be decided,
+    /// the path is where it would be under the default template.
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum OutFileAction {
+    Create,
+    Overwrite,
+    /// Create beside an existing file, under a suffixed name,
+    /// per `OverwritePolicy::KeepBoth`.
+    KeepBoth,
+    Skip(SkipReason),
+}
+
+#[derive(Serialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum SkipReason {
+    /// The output is newer than its input.
+    UpToDate,
+    /// The manifest shows the output was made
+    /// from the same input with the same settings.
+    Unchanged,
+    /// The output exists and the overwrite policy is `Skip`.
+    Exists,
 }
 
+impl OutFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn dsp(&self) -> &[DspStage] {
+        &self.dsp
+    }
+
+    pub fn encoder(&self) -> &EncoderSettings {
+        &self.encoder
+    }
+
+    pub fn action(&self) -> OutFileAction {
+        self.action
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// A hash of everything that affects the content of the output.
+    fn settings_hash(&self) -> String {
+        #[derive(Serialize)]
+        struct Settings<'a> {
+            format: Format,
+            dsp: &'a [DspStage],
+            encoder: &'a EncoderSettings,
+        }
+
+        let settings = Settings {
+            format: self.format,
+            dsp: &self.dsp,
+            encoder: &self.encoder,
+        };
+        let json = rmx::serde_json::to_string(&settings).expect("json");
+        rmx::blake3::hash(json.as_bytes()).to_hex().to_string()
+    }
+}
+
+/// The per-format parts of a config, parsed and compiled once per plan.
+struct OutputSpecs<'c> {
+    config: &'c Config,
+    tera: Tera,
+    /// The template name and encoder settings of each of `config.formats`.
+    formats: Vec<(String, EncoderSettings)>,
+    needs_props: bool,
+    needs_tags: bool,
+}
+
+const DEFAULT_TEMPLATE: &str = "default";
+
 impl Config {
-    fn outputs_for<'s>(&'s self, path: &'s Path) -> impl Iterator<Item = AnyResult<OutFile>> + 's {
-        self.formats.iter().copied().map(|format| {
-            Ok(OutFile {
-                path: self.outfile_for(path, format)?,
-                format,
-            })
+    fn output_specs(&self) -> AnyResult<OutputSpecs<'_>> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(DEFAULT_TEMPLATE, &self.out_path_template)
+            .context("invalid out_path_template")?;
+
+        let mut formats = vec![];
+        for (i, format_config) in self.formats.iter().enumerate() {
+            let name = match &format_config.out_path_template {
+                Some(template) => {
+                    let name = format!("format-{i}");
+                    tera.add_raw_template(&name, template)
+                        .with_context(|| format!("invalid out_path_template for format {i}"))?;
+                    name
+                }
+                None => S(DEFAULT_TEMPLATE),
+            };
+            formats.push((name, format_config.encoder_settings()?));
+        }
+
+        // Only open inputs if some template wants to know what's in them.
+        let mut vars = BTreeSet::new();
+        for name in tera.get_template_names() {
+            template_vars(&tera.get_template(name)?.ast, &mut vars);
+        }
+        let whole_context = vars.contains("__tera_context");
+        let needs_props = whole_context || vars.contains("channels");
+        let needs_tags = whole_context || vars.contains("tags");
+
+        Ok(OutputSpecs {
+            config: self,
+            tera,
+            formats,
+            needs_props,
+            needs_tags,
         })
     }
+}
+
+impl OutputSpecs<'_> {
+    /// The outputs of an input.
+    ///
+    /// Inputs that can't be probed, or whose paths can't be rendered,
+    /// fail only their own outputs, which carry the error.
+    fn outputs_for(&self, root: &InputRoot, path: &Path) -> Vec<OutFile> {
+        let input_vars = self.input_vars(path);
+
+        self.config.formats.iter().zip(&self.formats).map(|(format_config, (template, encoder))| {
+            let format = format_config.format;
+            let outfile = input_vars.as_ref()
+                .map_err(|e| anyhow!("{e:#}"))
+                .and_then(|(channels, tags)| {
+                    self.outfile_for(root, path, template, format, *channels, tags)
+                });
+            let (outfile, error) = match outfile {
+                Ok(outfile) => (outfile, None),
+                Err(e) => (self.fallback_outfile(root, path, format), Some(format!("{e:#}"))),
+            };
+            OutFile {
+                path: outfile,
+                format,
+                dsp: format_config.dsp.clone(),
+                encoder: encoder.clone(),
+                // Decided by the planner.
+                action: OutFileAction::Create,
+                error,
+            }
+        }).collect()
+    }
+
+    /// The channels and tags of an input, if the templates use them.
+    fn input_vars(&self, path: &Path) -> AnyResult<(Option<u16>, BTreeMap<String, String>)> {
+        let channels = if self.needs_props {
+            Some(codecs::probe(path).context("error probing input")?.channels)
+        } else {
+            None
+        };
+        let tags = if self.needs_tags {
+            tags_for_template(path).context("error reading tags")?
+        } else {
+            BTreeMap::new()
+        };
+        Ok((channels, tags))
+    }
 
-    fn outfile_for(&self, path: &Path, format: Format) -> AnyResult<PathBuf> {
+    fn outfile_for(
+        &self,
+        root: &InputRoot,
+        path: &Path,
+        template: &str,
+        format: Format,
+        channels: Option<u16>,
+        tags: &BTreeMap<String, String>,
+    ) -> AnyResult<PathBuf> {
         #[derive(Serialize)]
-        struct OutPathVars {
-            out_root_dir: PathBuf,
-            relative_path: PathBuf,
+        struct OutPathVars<'a> {
+            out_root_dir: String,
+            relative_path: String,
             file_stem: String,
             format_ext: String,
+            codec: &'static str,
+            sample_rate: u32,
+            bit_depth: u32,
+            channels: Option<u16>,
+            tags: &'a BTreeMap<String, String>,
         }
 
-        let outpath_vars = OutPathVars {
-            out_root_dir: self.out_root_dir.clone(),
-            relative_path: {
-                path.strip_prefix(&self.reference_tracks_dir)?
-                    .parent()
-                    .filter(|p| p != &Path::new(""))
-                    .unwrap_or(Path::new("."))
-                    .to_path_buf()
-            },
-            file_stem: if let Some(file_stem) = path.file_stem() {
-                file_stem.to_str().ok_or_else(|| {
-                    anyhow!("can't convert file stem to UTF-8")
-                })?.to_string()
-            } else {
-                bail!("no file stem")
-            },
-            format_ext: match format.codec {
-                Codec::Wav => "wav",
-                Codec::Flac => "flac",
-                Codec::Vorbis => "ogg",
-            }.to_string(),
+        let config = self.config;
+        let relative_path = relative_path(root, path)?;
+        let Some(file_stem) = path.file_stem() else {
+            bail!("no file stem")
         };
 
-        let mut tera = Tera::default();
-        tera.add_raw_template("template", &self.out_path_template)?;
+        let mut placeholders = PathPlaceholders::default();
+        let outpath_vars = OutPathVars {
+            out_root_dir: placeholders.var("out_root_dir", config.out_root_dir.as_os_str()),
+            relative_path: placeholders.var("relative_path", relative_path.as_os_str()),
+            file_stem: placeholders.var("file_stem", file_stem),
+            format_ext: format.codec.ext().to_string(),
+            codec: format.codec.name(),
+            sample_rate: format.sample_rate.as_u32(),
+            bit_depth: format.bit_depth.bits(),
+            channels,
+            tags,
+        };
 
         let context = TeraContext::from_serialize(&outpath_vars)?;
-        let path = tera.render("template", &context)?;
+        let path = self.tera.render(template, &context)?;
+
+        Ok(placeholders.substitute(&path))
+    }
+
+    /// Where an output would go under the default template,
+    /// to report it at when its own path can't be rendered.
+    fn fallback_outfile(&self, root: &InputRoot, path: &Path, format: Format) -> PathBuf {
+        let relative_path = relative_path(root, path).unwrap_or_default();
+        let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(format.codec.ext());
+        self.config.out_root_dir.join(relative_path).join(file_name)
+    }
+}
+
+/// The directory of an output relative to `out_root_dir`.
+fn relative_path(root: &InputRoot, path: &Path) -> AnyResult<PathBuf> {
+    let parent = path.strip_prefix(&root.dir)?
+        .parent()
+        .unwrap_or(Path::new(""));
+    Ok(match (root.out_dir.as_os_str().is_empty(), parent.as_os_str().is_empty()) {
+        (true, true) => PathBuf::from("."),
+        (false, true) => root.out_dir.clone(),
+        (_, false) => root.out_dir.join(parent),
+    })
+}
+
+/// Add the top-level variables `nodes` refer to to `vars`,
+/// like `tags` for `{{ tags.artist }}`.
+fn template_vars(nodes: &[Node], vars: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            Node::VariableBlock(_, expr) => expr_vars(expr, vars),
+            Node::Set(_, set) => expr_vars(&set.value, vars),
+            Node::FilterSection(_, section, _) => {
+                call_vars(&section.filter, vars);
+                template_vars(&section.body, vars);
+            }
+            Node::Block(_, block, _) => template_vars(&block.body, vars),
+            Node::MacroDefinition(_, definition, _) => {
+                definition.args.values().flatten().for_each(|expr| expr_vars(expr, vars));
+                template_vars(&definition.body, vars);
+            }
+            Node::Forloop(_, forloop, _) => {
+                expr_vars(&forloop.container, vars);
+                template_vars(&forloop.body, vars);
+                if let Some(body) = &forloop.empty_body {
+                    template_vars(body, vars);
+                }
+            }
+            Node::If(if_, _) => {
+                for (_, condition, body) in &if_.conditions {
+                    expr_vars(condition, vars);
+                    template_vars(body, vars);
+                }
+                if let Some((_, body)) = &if_.otherwise {
+                    template_vars(body, vars);
+                }
+            }
+            Node::Super
+            | Node::Text(_)
+            | Node::Extends(..)
+            | Node::Include(..)
+            | Node::ImportMacro(..)
+            | Node::Raw(..)
+            | Node::Break(_)
+            | Node::Continue(_)
+            | Node::Comment(..) => { }
+        }
+    }
+}
+
+fn expr_vars(expr: &Expr, vars: &mut BTreeSet<String>) {
+    expr_val_vars(&expr.val, vars);
+    expr.filters.iter().for_each(|filter| call_vars(filter, vars));
+}
+
+fn expr_val_vars(val: &ExprVal, vars: &mut BTreeSet<String>) {
+    match val {
+        ExprVal::Ident(ident) => ident_var(ident, vars),
+        ExprVal::Math(math) => {
+            expr_vars(&math.lhs, vars);
+            expr_vars(&math.rhs, vars);
+        }
+        ExprVal::Logic(logic) => {
+            expr_vars(&logic.lhs, vars);
+            expr_vars(&logic.rhs, vars);
+        }
+        ExprVal::Test(test) => {
+            ident_var(&test.ident, vars);
+            test.args.iter().for_each(|expr| expr_vars(expr, vars));
+        }
+        ExprVal::MacroCall(call) => call.args.values().for_each(|expr| expr_vars(expr, vars)),
+        ExprVal::FunctionCall(call) => call_vars(call, vars),
+        ExprVal::Array(exprs) => exprs.iter().for_each(|expr| expr_vars(expr, vars)),
+        ExprVal::StringConcat(concat) => concat.values.iter().for_each(|val| expr_val_vars(val, vars)),
+        ExprVal::In(in_) => {
+            expr_vars(&in_.lhs, vars);
+            expr_vars(&in_.rhs, vars);
+        }
+        ExprVal::String(_)
+        | ExprVal::Int(_)
+        | ExprVal::Float(_)
+        | ExprVal::Bool(_) => { }
+    }
+}
+
+fn call_vars(call: &FunctionCall, vars: &mut BTreeSet<String>) {
+    call.args.values().for_each(|expr| expr_vars(expr, vars));
+}
+
+/// The variable `tags.artist` or `tags["artist"]` looks up is `tags`.
+fn ident_var(ident: &str, vars: &mut BTreeSet<String>) {
+    let end = ident.find(['.', '[']).unwrap_or(ident.len());
+    vars.insert(ident[..end].to_string());
+}
+
+/// Tera only renders strings,
+/// so paths that aren't UTF-8 are rendered as placeholders,
+/// which are replaced with the real path afterwards.
+///
+/// Filters applied to such a variable see only the placeholder.
+#[derive(Default)]
+struct PathPlaceholders {
+    placeholders: Vec<(String, OsString)>,
+}
+
+impl PathPlaceholders {
+    fn var(&mut self, name: &str, value: &OsStr) -> String {
+        match value.to_str() {
+            Some(value) => value.to_string(),
+            None => {
+                // Noncharacters, which won't be in a template.
+                let placeholder = format!("\u{FDD0}{name}\u{FDD1}");
+                self.placeholders.push((placeholder.clone(), value.to_owned()));
+                placeholder
+            }
+        }
+    }
+
+    fn substitute(&self, rendered: &str) -> PathBuf {
+        let mut path = OsString::new();
+        let mut rest = rendered;
+        while let Some((start, placeholder, value)) = self.next_placeholder(rest) {
+            path.push(&rest[..start]);
+            path.push(value);
+            rest = &rest[start + placeholder.len()..];
+        }
+        path.push(rest);
+        PathBuf::from(path)
+    }
+
+    fn next_placeholder(&self, s: &str) -> Option<(usize, &str, &OsStr)> {
+        self.placeholders.iter()
+            .filter_map(|(placeholder, value)| {
+                s.find(placeholder.as_str())
+                    .map(|start| (start, placeholder.as_str(), value.as_os_str()))
+            })
+            .min_by_key(|(start, _, _)| *start)
+    }
+}
+
+/// Tags keyed by lowercase name, keeping the first of repeated tags,
+/// sanitized so each is a single ordinary path component.
+fn tags_for_template(path: &Path) -> AnyResult<BTreeMap<String, String>> {
+    let mut tags = BTreeMap::new();
+    for tag in info::read_tags(path)? {
+        tags.entry(tag.key).or_insert_with(|| sanitize_component(&tag.value));
+    }
+    Ok(tags)
+}
 
-        Ok(PathBuf::from(path))
+/// Replace path separators, so a value can't add directories,
+/// and values that would be empty, `.` or `..` components,
+/// so it can't collapse into or escape its parent.
+fn sanitize_component(value: &str) -> String {
+    let value = value.replace(['/', '\\', '\0'], "_");
+    if value.chars().all(|c| c == '.') {
+        "_".repeat(value.len().max(1))
+    } else {
+        value
     }
 }
@@ -0,0 +1,404 @@
+use rmx::prelude::*;
+use rmx::serde::{Serialize, Deserialize};
+use crate::types::SampleRate;
+use crate::io::Buf;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+#[derive(Serialize, Deserialize)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum DspStage {
+    Gain {
+        db: f64,
+    },
+    FadeIn {
+        length: Length,
+        #[serde(default)]
+        curve: FadeCurve,
+    },
+    FadeOut {
+        length: Length,
+        #[serde(default)]
+        curve: FadeCurve,
+    },
+    Trim {
+        #[serde(default)]
+        head: Length,
+        #[serde(default)]
+        tail: Length,
+    },
+    RemoveDc,
+    /// Trim leading and trailing silence.
+    ///
+    /// Frames where every channel is below `threshold_db` (dBFS) are silent.
+    /// Up to `pad` frames of silence are kept at each end.
+    TrimSilence {
+        threshold_db: f64,
+        #[serde(default)]
+        pad: Length,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub enum Length {
+    /// Sample frames, i.e. samples per channel.
+    Samples(u64),
+    Seconds(f64),
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    EqualPower,
+    Exponential,
+    Logarithmic,
+}
+
+/// Where silence trimming cut the stream,
+/// in frames of the input to the trim stage.
+#[derive(Serialize, Deserialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct SilenceTrim {
+    /// The first frame kept.
+    pub start_frame: u64,
+    /// One past the last frame kept.
+    pub end_frame: u64,
+    pub total_frames: u64,
+}
+
+#[derive(Default)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct DspReport {
+    pub silence_trim: Option<SilenceTrim>,
+}
+
+impl Default for Length {
+    fn default() -> Length {
+        Length::Samples(0)
+    }
+}
+
+impl Length {
+    pub fn frames(&self, sample_rate: SampleRate) -> u64 {
+        match self {
+            Length::Samples(frames) => *frames,
+            Length::Seconds(secs) => {
+                (secs.max(0.0) * sample_rate.as_u32() as f64).round() as u64
+            }
+        }
+    }
+}
+
+impl FadeCurve {
+    /// Gain for a position `t` in `0.0..=1.0` through a fade-in.
+    pub fn gain(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * PI / 2.0).sin(),
+            FadeCurve::Exponential => t * t,
+            FadeCurve::Logarithmic => 1.0 - (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+/// An ordered chain of DSP stages run over interleaved `F32` buffers.
+///
+/// Stages that need to see the end of the stream,
+/// like fade-out and tail trimming,
+/// hold back only as many frames as they need,
+/// and release them from `finish`.
+pub struct DspChain {
+    stages: Vec<Stage>,
+    outbuf: Buf,
+}
+
+enum Stage {
+    Gain {
+        factor: f32,
+    },
+    FadeIn {
+        frames: u64,
+        pos: u64,
+        curve: FadeCurve,
+        channels: usize,
+    },
+    FadeOut {
+        curve: FadeCurve,
+        delay: Delay,
+    },
+    Trim {
+        head_remaining: u64,
+        delay: Delay,
+    },
+    RemoveDc {
+        coeff: f64,
+        // Previous input and output sample per channel.
+        state: Vec<(f64, f64)>,
+    },
+    TrimSilence {
+        threshold: f32,
+        pad_frames: usize,
+        channels: usize,
+        started: bool,
+        frames_in: u64,
+        frames_out: u64,
+        // Before the first loud frame, the last `pad_frames` frames of silence.
+        // After, all silence since the last loud frame.
+        held: VecDeque<f32>,
+        scratch: Vec<f32>,
+        report: Option<SilenceTrim>,
+    },
+}
+
+struct Delay {
+    frames: usize,
+    channels: usize,
+    held: VecDeque<f32>,
+}
+
+impl DspChain {
+    pub fn new(
+        stages: &[DspStage],
+        channels: u16,
+        sample_rate: SampleRate,
+    ) -> DspChain {
+        let channels = channels as usize;
+        let stages = stages.iter().map(|stage| {
+            match stage {
+                DspStage::Gain { db } => Stage::Gain {
+                    factor: 10_f64.powf(db / 20.0) as f32,
+                },
+                DspStage::FadeIn { length, curve } => Stage::FadeIn {
+                    frames: length.frames(sample_rate),
+                    pos: 0,
+                    curve: *curve,
+                    channels,
+                },
+                DspStage::FadeOut { length, curve } => Stage::FadeOut {
+                    curve: *curve,
+                    delay: Delay::new(length.frames(sample_rate), channels),
+                },
+                DspStage::Trim { head, tail } => Stage::Trim {
+                    head_remaining: head.frames(sample_rate),
+                    delay: Delay::new(tail.frames(sample_rate), channels),
+                },
+                DspStage::RemoveDc => {
+                    // One-pole high-pass with a cutoff well below audible.
+                    let cutoff_hz = 5.0;
+                    Stage::RemoveDc {
+                        coeff: 1.0 - (2.0 * PI * cutoff_hz / sample_rate.as_u32() as f64),
+                        state: vec![(0.0, 0.0); channels],
+                    }
+                }
+                DspStage::TrimSilence { threshold_db, pad } => Stage::TrimSilence {
+                    threshold: 10_f64.powf(threshold_db / 20.0) as f32,
+                    pad_frames: pad.frames(sample_rate) as usize,
+                    channels,
+                    started: false,
+                    frames_in: 0,
+                    frames_out: 0,
+                    held: VecDeque::new(),
+                    scratch: vec![],
+                    report: None,
+                },
+            }
+        }).collect();
+
+        DspChain {
+            stages,
+            outbuf: Buf::Uninit,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn process<'a>(&'a mut self, inbuf: &'a Buf) -> &'a Buf {
+        if self.stages.is_empty() {
+            return inbuf;
+        }
+        match inbuf {
+            Buf::F32(inbuf) => {
+                let outbuf = self.outbuf.f32_mut();
+                outbuf.truncate(0);
+                outbuf.extend_from_slice(inbuf);
+                for stage in &mut self.stages {
+                    stage.process(outbuf);
+                }
+                &self.outbuf
+            }
+            _ => panic!(),
+        }
+    }
+
+    /// Release any frames held back by the stages at end of stream,
+    /// and report what the stages found.
+    pub fn finish(&mut self) -> (&Buf, DspReport) {
+        let outbuf = self.outbuf.f32_mut();
+        outbuf.truncate(0);
+        // Each stage processes what earlier stages released,
+        // then appends what it was holding itself.
+        for stage in &mut self.stages {
+            stage.process(outbuf);
+            stage.finish(outbuf);
+        }
+
+        let mut report = DspReport::default();
+        for stage in &self.stages {
+            if let Stage::TrimSilence { report: Some(silence_trim), .. } = stage {
+                report.silence_trim = Some(*silence_trim);
+            }
+        }
+
+        (&self.outbuf, report)
+    }
+}
+
+impl Stage {
+    fn process(&mut self, buf: &mut Vec<f32>) {
+        match self {
+            Stage::Gain { factor } => {
+                for sample in buf.iter_mut() {
+                    *sample *= *factor;
+                }
+            }
+            Stage::FadeIn { frames, pos, curve, channels } => {
+                for frame in buf.chunks_exact_mut(*channels) {
+                    if *pos >= *frames {
+                        break;
+                    }
+                    let gain = curve.gain(*pos as f64 / *frames as f64) as f32;
+                    for sample in frame {
+                        *sample *= gain;
+                    }
+                    *pos += 1;
+                }
+            }
+            Stage::FadeOut { delay, .. } => {
+                delay.process(buf);
+            }
+            Stage::Trim { head_remaining, delay } => {
+                let frames = (buf.len() / delay.channels) as u64;
+                let drop_frames = frames.min(*head_remaining);
+                buf.drain(..drop_frames as usize * delay.channels);
+                *head_remaining -= drop_frames;
+                delay.process(buf);
+            }
+            Stage::RemoveDc { coeff, state } => {
+                let channels = state.len();
+                for frame in buf.chunks_exact_mut(channels) {
+                    for (sample, (prev_in, prev_out)) in frame.iter_mut().zip(state.iter_mut()) {
+                        let input = *sample as f64;
+                        let output = input - *prev_in + *coeff * *prev_out;
+                        *prev_in = input;
+                        *prev_out = output;
+                        *sample = output as f32;
+                    }
+                }
+            }
+            Stage::TrimSilence {
+                threshold, pad_frames, channels,
+                started, frames_in, frames_out, held, scratch, ..
+            } => {
+                let channels = *channels;
+                scratch.truncate(0);
+                for frame in buf.chunks_exact(channels) {
+                    *frames_in += 1;
+                    let loud = frame.iter().any(|s| s.abs() > *threshold);
+                    if loud {
+                        // Release the held silence, which is either
+                        // the head padding or a gap in the audio.
+                        *frames_out += (held.len() / channels) as u64 + 1;
+                        scratch.extend(held.drain(..));
+                        scratch.extend_from_slice(frame);
+                        *started = true;
+                    } else {
+                        held.extend(frame);
+                        if !*started && held.len() > *pad_frames * channels {
+                            held.drain(..channels);
+                        }
+                    }
+                }
+                std::mem::swap(buf, scratch);
+            }
+        }
+    }
+
+    fn finish(&mut self, buf: &mut Vec<f32>) {
+        match self {
+            Stage::Gain { .. } | Stage::FadeIn { .. } | Stage::RemoveDc { .. } => { }
+            Stage::FadeOut { curve, delay } => {
+                let channels = delay.channels;
+                let held_frames = delay.held.len() / channels;
+                let fade_frames = delay.frames;
+                for (i, sample) in delay.held.drain(..).enumerate() {
+                    let frames_after = held_frames - i / channels - 1;
+                    let gain = curve.gain(frames_after as f64 / fade_frames as f64) as f32;
+                    buf.push(sample * gain);
+                }
+            }
+            Stage::Trim { delay, .. } => {
+                delay.held.clear();
+            }
+            Stage::TrimSilence {
+                pad_frames, channels,
+                started, frames_in, frames_out, held, report, ..
+            } => {
+                let held_frames = held.len() / *channels;
+                let (start_frame, end_frame) = if *started {
+                    let pad = held_frames.min(*pad_frames);
+                    buf.extend(held.drain(..pad * *channels));
+                    *frames_out += pad as u64;
+                    let end_frame = *frames_in - (held_frames - pad) as u64;
+                    (end_frame - *frames_out, end_frame)
+                } else {
+                    // All silence.
+                    (*frames_in, *frames_in)
+                };
+                held.clear();
+                *report = Some(SilenceTrim {
+                    start_frame,
+                    end_frame,
+                    total_frames: *frames_in,
+                });
+            }
+        }
+    }
+}
+
+impl Delay {
+    fn new(frames: u64, channels: usize) -> Delay {
+        Delay {
+            frames: frames as usize,
+            channels,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Hold back the last `frames` frames seen and
+    /// replace `buf` with whatever is older than that.
+    fn process(&mut self, buf: &mut Vec<f32>) {
+        if self.frames == 0 {
+            return;
+        }
+        self.held.extend(buf.drain(..));
+        let keep = self.frames * self.channels;
+        let release = self.held.len().saturating_sub(keep);
+        buf.extend(self.held.drain(..release));
+    }
+}
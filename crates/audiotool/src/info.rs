@@ -0,0 +1,306 @@
+//! Inspecting audio files.
+
+use rmx::prelude::*;
+use rmx::serde::Serialize;
+use crate::types::Codec;
+use crate::io::Props;
+use crate::codecs::{self, Container};
+use std::path::{Path, PathBuf};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::fs::{self, File};
+
+#[derive(Serialize)]
+#[derive(Debug)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub container: Container,
+    pub props: Props,
+    /// `None` if the container doesn't record the length.
+    pub frames: Option<u64>,
+    pub duration_secs: Option<f64>,
+    pub file_size: u64,
+    /// Average over the whole file, including metadata.
+    pub bitrate: Option<u64>,
+    /// Keys are lowercase Vorbis comment names, e.g. `artist`.
+    pub tags: Vec<Tag>,
+    /// The STREAMINFO MD5 signature of a FLAC file, in hex,
+    /// if it has one.
+    pub md5: Option<String>,
+    /// Whether the decoded audio matches `md5`.
+    pub md5_verified: Option<bool>,
+    /// RIFF chunks or FLAC metadata blocks.
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Serialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+#[derive(Eq, PartialEq)]
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Chunk {
+    pub id: String,
+    /// Byte offset of the chunk header.
+    pub offset: u64,
+    /// Size of the chunk data, not including the header.
+    pub size: u64,
+}
+
+/// Everything we can find out about a supported file.
+///
+/// This decodes FLAC files completely to verify their MD5 signatures.
+pub fn file_info(path: &Path) -> AnyResult<FileInfo> {
+    let container = codecs::detect_container(path)?
+        .ok_or_else(|| anyhow!("unrecognized format"))?;
+    let codec = container.codec()
+        .ok_or_else(|| anyhow!("unsupported format: {container}"))?;
+
+    let mut reader = codecs::codec_reader(path, codec);
+    let props = reader.props()?;
+    let frames = reader.total_frames()?;
+    drop(reader);
+
+    let file_size = fs::metadata(path)?.len();
+    let duration_secs = frames.map(|frames| {
+        frames as f64 / props.format.sample_rate.as_u32() as f64
+    });
+    let bitrate = duration_secs
+        .filter(|secs| *secs > 0.0)
+        .map(|secs| (file_size as f64 * 8.0 / secs).round() as u64);
+
+    let metadata = read_metadata(path, codec)?;
+
+    let md5_verified = match &metadata.md5 {
+        Some(_) => Some(codecs::verify_flac_md5(path)?),
+        None => None,
+    };
+
+    Ok(FileInfo {
+        path: path.to_owned(),
+        container,
+        props,
+        frames,
+        duration_secs,
+        file_size,
+        bitrate,
+        tags: metadata.tags,
+        md5: metadata.md5,
+        md5_verified,
+        chunks: metadata.chunks,
+    })
+}
+
+/// The metadata tags of a supported file.
+pub fn read_tags(path: &Path) -> AnyResult<Vec<Tag>> {
+    let container = codecs::detect_container(path)?;
+    match container.and_then(|container| container.codec()) {
+        Some(codec) => Ok(read_metadata(path, codec)?.tags),
+        None => Ok(vec![]),
+    }
+}
+
+#[derive(Default)]
+struct Metadata {
+    tags: Vec<Tag>,
+    md5: Option<String>,
+    chunks: Vec<Chunk>,
+}
+
+fn read_metadata(path: &Path, codec: Codec) -> AnyResult<Metadata> {
+    let mut file = BufReader::new(File::open(path)?);
+    match codec {
+        Codec::Wav => read_riff_metadata(&mut file),
+        Codec::Flac => read_flac_metadata(&mut file),
+        Codec::Vorbis => Ok(Metadata::default()),
+    }
+}
+
+fn read_riff_metadata(file: &mut (impl Read + Seek)) -> AnyResult<Metadata> {
+    let mut metadata = Metadata::default();
+
+    let file_len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    let riff_end = 8 + u32::from_le_bytes(header[4..8].try_into().expect("4")) as u64;
+    let mut offset = 12;
+
+    while offset + 8 <= riff_end {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut chunk_header = [0; 8];
+        if file.read_exact(&mut chunk_header).is_err() {
+            // Truncated.
+            break;
+        }
+        let id = fourcc(&chunk_header[0..4]);
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().expect("4")) as u64;
+
+        if id == "LIST" {
+            // The size is untrusted, so check it before allocating.
+            if offset + 8 + size > file_len {
+                bail!("{id} chunk at offset {offset} claims {size} bytes, past the end of the file");
+            }
+            let mut data = vec![0; size as usize];
+            file.read_exact(&mut data)?;
+            if data.starts_with(b"INFO") {
+                metadata.tags.extend(riff_info_tags(&data[4..]));
+            }
+        }
+
+        metadata.chunks.push(Chunk { id, offset, size });
+
+        // Chunks are padded to even sizes.
+        offset += 8 + size + size % 2;
+    }
+
+    Ok(metadata)
+}
+
+fn riff_info_tags(mut data: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+
+    while data.len() >= 8 {
+        let id = fourcc(&data[0..4]);
+        let size = u32::from_le_bytes(data[4..8].try_into().expect("4")) as usize;
+        let Some(value) = data.get(8..8 + size) else {
+            break;
+        };
+        let value = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .to_string();
+
+        let key = match id.as_str() {
+            "INAM" => S("title"),
+            "IART" => S("artist"),
+            "IPRD" => S("album"),
+            "ITRK" | "IPRT" => S("tracknumber"),
+            "ICRD" => S("date"),
+            "IGNR" => S("genre"),
+            "ICMT" => S("comment"),
+            "ICOP" => S("copyright"),
+            "ISFT" => S("encoder"),
+            other => other.to_lowercase(),
+        };
+        tags.push(Tag { key, value });
+
+        let next = (8 + size + size % 2).min(data.len());
+        data = &data[next..];
+    }
+
+    tags
+}
+
+fn read_flac_metadata(file: &mut (impl Read + Seek)) -> AnyResult<Metadata> {
+    let mut metadata = Metadata::default();
+
+    // Skip any ID3v2 tag before the stream marker.
+    let mut marker = [0; 10];
+    file.read_exact(&mut marker[..4])?;
+    let mut offset = 4;
+    if &marker[..3] == b"ID3" {
+        file.read_exact(&mut marker[4..])?;
+        let size = marker[6..10].iter()
+            .fold(0_u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+        offset = 10 + size;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut marker[..4])?;
+        offset += 4;
+    }
+    if &marker[..4] != b"fLaC" {
+        bail!("missing FLAC stream marker");
+    }
+
+    loop {
+        let mut block_header = [0; 4];
+        file.read_exact(&mut block_header)?;
+        let last = block_header[0] & 0x80 != 0;
+        let block_type = block_header[0] & 0x7f;
+        let size = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]) as u64;
+
+        let id = match block_type {
+            0 => S("STREAMINFO"),
+            1 => S("PADDING"),
+            2 => S("APPLICATION"),
+            3 => S("SEEKTABLE"),
+            4 => S("VORBIS_COMMENT"),
+            5 => S("CUESHEET"),
+            6 => S("PICTURE"),
+            other => format!("UNKNOWN({other})"),
+        };
+
+        match block_type {
+            0 | 4 => {
+                let mut data = vec![0; size as usize];
+                file.read_exact(&mut data)?;
+                if block_type == 0 {
+                    // An all-zero signature means the encoder didn't compute one.
+                    let md5 = data.get(18..34)
+                        .ok_or_else(|| anyhow!("short STREAMINFO block"))?;
+                    if md5.iter().any(|byte| *byte != 0) {
+                        metadata.md5 = Some(rmx::hex::encode(md5));
+                    }
+                } else {
+                    metadata.tags.extend(vorbis_comments(&data)?);
+                }
+            }
+            _ => {
+                file.seek(SeekFrom::Current(size as i64))?;
+            }
+        }
+
+        metadata.chunks.push(Chunk { id, offset, size });
+        offset += 4 + size;
+
+        if last {
+            break;
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn vorbis_comments(mut data: &[u8]) -> AnyResult<Vec<Tag>> {
+    let vendor_len = take_u32_le(&mut data)?;
+    take(&mut data, vendor_len)?;
+    let count = take_u32_le(&mut data)?;
+
+    let mut tags = vec![];
+    for _ in 0..count {
+        let len = take_u32_le(&mut data)?;
+        let comment = String::from_utf8_lossy(take(&mut data, len)?);
+        if let Some((key, value)) = comment.split_once('=') {
+            tags.push(Tag {
+                key: key.to_lowercase(),
+                value: value.to_string(),
+            });
+        }
+    }
+
+    Ok(tags)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> AnyResult<&'a [u8]> {
+    if data.len() < len {
+        bail!("truncated VORBIS_COMMENT block");
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+fn take_u32_le(data: &mut &[u8]) -> AnyResult<usize> {
+    let bytes = take(data, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().expect("4")) as usize)
+}
+
+fn fourcc(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
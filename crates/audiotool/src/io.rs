@@ -1,4 +1,5 @@
 use rmx::prelude::*;
+use rmx::serde::{Serialize, Deserialize};
 use crate::types::*;
 
 #[derive(PartialEq, Debug)]
@@ -9,15 +10,29 @@ pub enum Buf {
     I16(Vec<i16>),
 }
 
+#[derive(Serialize, Deserialize)]
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub struct Props {
     pub channels: u16,
     pub format: Format,
 }
 
+/// Frames per `PcmReader::read` unless set otherwise.
+pub const DEFAULT_BLOCK_FRAMES: usize = 4096;
+
 pub trait PcmReader: Send {
     fn props(&mut self) -> AnyResult<Props>;
 
+    /// Set how many frames each `read` returns.
+    ///
+    /// Every block is this long except the last before EOF,
+    /// which may be shorter.
+    /// Defaults to `DEFAULT_BLOCK_FRAMES`.
+    fn set_block_frames(&mut self, frames: usize);
+
+    /// The length of the stream in frames, if the container records it.
+    fn total_frames(&mut self) -> AnyResult<Option<u64>>;
+
     fn read(
         &mut self,
         buf: &mut Buf,
@@ -33,6 +48,15 @@ pub trait PcmWriter: Send {
     fn finalize(&mut self) -> AnyResult<()>;
 }
 
+/// A streaming hash of PCM samples,
+/// for comparing audio independent of how it is encoded.
+#[derive(Default)]
+pub struct PcmHasher {
+    hasher: rmx::blake3::Hasher,
+    samples: u64,
+    scratch: Vec<u8>,
+}
+
 fn static_assertions(
     reader: &dyn PcmReader,
     writer: &dyn PcmWriter,
@@ -107,6 +131,29 @@ impl Buf {
         }
     }
 
+    /// Move up to `samples` samples from the front of `self` into `out`,
+    /// replacing what `out` had.
+    pub fn drain_front_into(&mut self, samples: usize, out: &mut Buf) {
+        match self {
+            Buf::Uninit => out.truncate(),
+            Buf::F32(buf) => {
+                let out = out.f32_mut();
+                out.truncate(0);
+                out.extend(buf.drain(..samples.min(buf.len())));
+            }
+            Buf::I24(buf) => {
+                let out = out.i24_mut();
+                out.truncate(0);
+                out.extend(buf.drain(..samples.min(buf.len())));
+            }
+            Buf::I16(buf) => {
+                let out = out.i16_mut();
+                out.truncate(0);
+                out.extend(buf.drain(..samples.min(buf.len())));
+            }
+        }
+    }
+
     pub fn truncate(&mut self) {
         match self {
             Buf::Uninit => { },
@@ -133,3 +180,47 @@ impl Props {
         true
     }
 }
+
+impl PcmHasher {
+    pub fn new() -> PcmHasher {
+        PcmHasher {
+            hasher: rmx::blake3::Hasher::new(),
+            samples: 0,
+            scratch: vec![],
+        }
+    }
+
+    pub fn update(&mut self, buf: &Buf) {
+        self.scratch.truncate(0);
+        match buf {
+            Buf::Uninit => { }
+            Buf::F32(buf) => {
+                for sample in buf {
+                    self.scratch.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            Buf::I24(buf) => {
+                for sample in buf {
+                    self.scratch.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            Buf::I16(buf) => {
+                for sample in buf {
+                    self.scratch.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+        self.hasher.update(&self.scratch);
+        if !buf.is_empty() {
+            self.samples += buf.len() as u64;
+        }
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn hash(&self) -> rmx::blake3::Hash {
+        self.hasher.finalize()
+    }
+}
@@ -7,4 +7,7 @@ pub mod types;
 pub mod codecs;
 pub mod bitdepth;
 pub mod samplerate;
+pub mod dsp;
+pub mod stats;
+pub mod info;
 pub mod testsupport;
@@ -0,0 +1,79 @@
+use rmx::prelude::*;
+use rmx::serde::{Serialize, Deserialize};
+use crate::types::SampleRate;
+use crate::io::Buf;
+
+/// Accumulates level statistics over a stream of `F32` buffers.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct SignalStats {
+    channels: u16,
+    samples: u64,
+    peak: f32,
+    sum_squares: f64,
+    overs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct OutputStats {
+    pub frames: u64,
+    pub duration_secs: f64,
+    /// `None` for digital silence.
+    pub peak_dbfs: Option<f64>,
+    pub rms_dbfs: Option<f64>,
+    /// Samples beyond full scale, which will clip in integer formats.
+    pub overs: u64,
+}
+
+impl SignalStats {
+    pub fn new(channels: u16) -> SignalStats {
+        SignalStats {
+            channels,
+            samples: 0,
+            peak: 0.0,
+            sum_squares: 0.0,
+            overs: 0,
+        }
+    }
+
+    pub fn update(&mut self, buf: &Buf) {
+        match buf {
+            Buf::F32(buf) => {
+                for sample in buf.iter().copied() {
+                    let abs = sample.abs();
+                    self.peak = self.peak.max(abs);
+                    self.sum_squares += (sample as f64) * (sample as f64);
+                    if abs > 1.0 {
+                        self.overs += 1;
+                    }
+                }
+                self.samples += buf.len() as u64;
+            }
+            Buf::Uninit => { }
+            _ => panic!(),
+        }
+    }
+
+    pub fn summary(&self, sample_rate: SampleRate) -> OutputStats {
+        let frames = self.samples / self.channels as u64;
+        let rms = if self.samples > 0 {
+            (self.sum_squares / self.samples as f64).sqrt()
+        } else {
+            0.0
+        };
+        OutputStats {
+            frames,
+            duration_secs: frames as f64 / sample_rate.as_u32() as f64,
+            peak_dbfs: to_dbfs(self.peak as f64),
+            rms_dbfs: to_dbfs(rms),
+            overs: self.overs,
+        }
+    }
+}
+
+fn to_dbfs(level: f64) -> Option<f64> {
+    (level > 0.0).then(|| 20.0 * level.log10())
+}
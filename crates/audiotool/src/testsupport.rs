@@ -2,6 +2,7 @@ use rmx::prelude::*;
 use rmx::rand_pcg::Pcg64Mcg;
 use rmx::rand::Rng;
 use rmx::itertools::Itertools;
+use rmx::tempfile::TempDir;
 use std::path::Path;
 use std::iter;
 use crate::types::*;
@@ -41,13 +42,39 @@ pub fn write_test_file(
         }
     };
 
-    let mut writer = codecs::writer(path, props);
+    let settings = codecs::EncoderSettings::default_for(props.format.codec);
+    let mut writer = codecs::writer(path, props, &settings)?;
     writer.write(&buf)?;
     writer.finalize()?;
 
     Ok(buf)
 }
 
+/// Append a LIST/INFO chunk of `tags` to a WAV file,
+/// fixing up the RIFF size, and return the file's bytes.
+pub fn append_riff_info(path: &Path, tags: &[(&[u8; 4], &str)]) -> AnyResult<Vec<u8>> {
+    let mut bytes = std::fs::read(path)?;
+    let mut list = b"INFO".to_vec();
+    for (id, value) in tags {
+        // NUL-terminated, padded to an even size.
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        if value.len() % 2 != 0 {
+            value.push(0);
+        }
+        list.extend(*id);
+        list.extend((value.len() as u32).to_le_bytes());
+        list.extend(value);
+    }
+    bytes.extend(b"LIST");
+    bytes.extend((list.len() as u32).to_le_bytes());
+    bytes.extend(&list);
+    let riff_size = bytes.len() as u32 - 8;
+    bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    std::fs::write(path, &bytes)?;
+    Ok(bytes)
+}
+
 pub fn read_file(path: &Path) -> AnyResult<(Props, Buf)> {
     #[extension_trait]
     impl BufExt for Buf {
@@ -93,23 +120,35 @@ pub fn read_file(path: &Path) -> AnyResult<(Props, Buf)> {
     Ok((reader.props()?, all_buf))
 }
 
-pub fn run_convert(config: cvt::config::Config) -> AnyResult<()> {
+pub fn run_plan(config: cvt::config::Config) -> AnyResult<cvt::plan::Plan> {
     let (_tx, rx) = cvt::plan::spawn(config);
 
-    let plan = match rx.recv().expect("recv") {
-        cvt::plan::Response::Done(Ok(Some(plan))) => plan,
+    match rx.recv().expect("recv") {
+        cvt::plan::Response::Done(Ok(Some(plan))) => Ok(plan),
         cvt::plan::Response::Done(Ok(None)) => panic!(),
         cvt::plan::Response::Done(Err(e)) => panic!("{e}"),
-    };
+    }
+}
 
+pub fn run_convert(config: cvt::config::Config) -> AnyResult<Vec<cvt::exec::ConvertResult>> {
+    let plan = run_plan(config)?;
+    run_exec(plan)
+}
+
+pub fn run_exec(plan: cvt::plan::Plan) -> AnyResult<Vec<cvt::exec::ConvertResult>> {
     let (_tx, rx) = cvt::exec::spawn(plan);
+    let mut results = vec![];
 
     loop {
         let resp = rx.recv()?;
 
         match resp {
-            cvt::exec::Response::NextResult(_res) => {
+            cvt::exec::Response::FileStarted { .. }
+            | cvt::exec::Response::Progress { .. }
+            | cvt::exec::Response::FileFinished { .. } => { }
+            cvt::exec::Response::NextResult(res) => {
                 //println!("{res:#?}");
+                results.push(res);
             }
             cvt::exec::Response::Done => {
                 break;
@@ -120,18 +159,74 @@ pub fn run_convert(config: cvt::config::Config) -> AnyResult<()> {
         }
     }
 
-    Ok(())
+    Ok(results)
+}
+
+pub fn test_config(
+    dir: &Path,
+    inprops: Props,
+    outformat: Format,
+) -> cvt::config::Config {
+    cvt::config::Config {
+        reference_tracks_dir: dir.join("in"),
+        reference_track_regex: format!("\\.{}$", inprops.format.codec.ext()),
+        include: vec![],
+        exclude: vec![],
+        inputs: vec![],
+        follow_symlinks: false,
+        out_root_dir: dir.join("out"),
+        out_path_template: S("{{out_root_dir}}/{{relative_path}}/{{file_stem}}.{{format_ext}}"),
+        formats: vec![outformat.into()],
+        verify: false,
+        incremental: cvt::config::Incremental::Off,
+        overwrite: cvt::config::OverwritePolicy::Overwrite,
+        limits: cvt::config::Limits::default(),
+    }
 }
 
-#[extension_trait]
-impl CodecExt for Codec {
-    fn ext(&self) -> &'static str {
-        match self {
-            Codec::Wav => "wav",
-            Codec::Flac => "flac",
-            Codec::Vorbis => "ogg",
+/// The formats converted by `test_tree`,
+/// for tests that don't need particular ones.
+pub fn test_formats() -> (Props, Format) {
+    (
+        Props {
+            channels: 2,
+            format: Format {
+                codec: Codec::Wav,
+                bit_depth: BitDepth::I24,
+                sample_rate: SampleRate::K48,
+            },
+        },
+        Format {
+            codec: Codec::Flac,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    )
+}
+
+/// A temporary directory with a `test_config` converting `test_formats`,
+/// and a test file of `frames` frames at each of `inputs`,
+/// relative to the input directory, which always exists.
+///
+/// Everything is deleted when the `TempDir` is dropped.
+pub fn test_tree(
+    inputs: &[&str],
+    frames: u32,
+) -> AnyResult<(TempDir, cvt::config::Config)> {
+    let tempdir = TempDir::with_prefix("audiotool")?;
+    let (inprops, outformat) = test_formats();
+    let config = test_config(tempdir.path(), inprops, outformat);
+
+    std::fs::create_dir_all(&config.reference_tracks_dir)?;
+    for input in inputs {
+        let path = config.reference_tracks_dir.join(input);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
         }
+        write_test_file(&path, inprops, frames)?;
     }
+
+    Ok((tempdir, config))
 }
 
 pub fn test_basic(
@@ -139,13 +234,7 @@ pub fn test_basic(
     outformat: Format,
 ) -> AnyResult<()> {
     let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
-    let config = cvt::config::Config {
-        reference_tracks_dir: tempdir.path().join("in"),
-        reference_track_regex: format!("\\.{}$", inprops.format.codec.ext()),
-        out_root_dir: tempdir.path().join("out"),
-        out_path_template: S("{{out_root_dir}}/{{relative_path}}/{{file_stem}}.{{format_ext}}"),
-        formats: vec![outformat],
-    };
+    let config = test_config(tempdir.path(), inprops, outformat);
 
     std::fs::create_dir_all(&config.reference_tracks_dir)?;
 
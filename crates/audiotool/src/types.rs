@@ -1,5 +1,6 @@
 use rmx::prelude::*;
 use rmx::serde::{Serialize, Deserialize};
+use std::str::FromStr;
 
 pub trait SampleFormat {
     type Type;
@@ -50,6 +51,52 @@ pub enum SampleRate {
     K48,
 }
 
+impl Codec {
+    /// The codec conventionally written with a file extension.
+    pub fn from_ext(ext: &str) -> Option<Codec> {
+        match ext.to_ascii_lowercase().as_str() {
+            "wav" => Some(Codec::Wav),
+            "flac" => Some(Codec::Flac),
+            "ogg" => Some(Codec::Vorbis),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Wav => "wav",
+            Codec::Flac => "flac",
+            Codec::Vorbis => "vorbis",
+        }
+    }
+
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Codec::Wav => "wav",
+            Codec::Flac => "flac",
+            Codec::Vorbis => "ogg",
+        }
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        match self {
+            Codec::Wav => true,
+            Codec::Flac => true,
+            Codec::Vorbis => false,
+        }
+    }
+}
+
+impl BitDepth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            BitDepth::F32 => 32,
+            BitDepth::I24 => 24,
+            BitDepth::I16 => 16,
+        }
+    }
+}
+
 impl SampleRate {
     pub fn as_u32(&self) -> u32 {
         match self {
@@ -58,3 +105,30 @@ impl SampleRate {
         }
     }
 }
+
+/// Parses `f32`, `i24` or `i16`.
+impl FromStr for BitDepth {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> AnyResult<BitDepth> {
+        match s.to_ascii_lowercase().as_str() {
+            "f32" => Ok(BitDepth::F32),
+            "i24" => Ok(BitDepth::I24),
+            "i16" => Ok(BitDepth::I16),
+            _ => bail!("unknown bit depth `{s}`, expected one of f32, i24, i16"),
+        }
+    }
+}
+
+/// Parses Hz, like `48000`, or kHz, like `48k`.
+impl FromStr for SampleRate {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> AnyResult<SampleRate> {
+        match s.to_ascii_lowercase().as_str() {
+            "192k" | "192khz" | "192000" => Ok(SampleRate::K192),
+            "48k" | "48khz" | "48000" => Ok(SampleRate::K48),
+            _ => bail!("unsupported sample rate `{s}`, expected 48k or 192k"),
+        }
+    }
+}
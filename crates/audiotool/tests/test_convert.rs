@@ -1,6 +1,8 @@
 use rmx::prelude::*;
 use audiotool::types::*;
 use audiotool::io::Props;
+use audiotool::codecs::EncoderSettings;
+use audiotool::convert::config::OverwritePolicy;
 use audiotool::testsupport::*;
 
 #[test]
@@ -59,3 +61,1026 @@ fn basic_flac_flac() -> AnyResult<()> {
         },
     )
 }
+
+#[test]
+fn verified_conversion() -> AnyResult<()> {
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.verify = true;
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].error.is_ok());
+    assert!(results[0].out_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn verify_output_detects_damage() -> AnyResult<()> {
+    use audiotool::convert::exec::verify_output;
+    use audiotool::io::PcmHasher;
+
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.wav");
+    let (inprops, _) = test_formats();
+    // WAV has no checksums, so damage is only caught by comparing samples.
+    let buf = write_test_file(&path, inprops, 1024)?;
+    let mut written = PcmHasher::new();
+    written.update(&buf);
+
+    verify_output(&path, inprops, &written)?;
+
+    let mut bytes = std::fs::read(&path)?;
+    *bytes.last_mut().expect("bytes") ^= 0xff;
+    std::fs::write(&path, bytes)?;
+    let e = verify_output(&path, inprops, &written).expect_err("damaged output");
+    assert!(e.to_string().contains("samples read back differ"), "{e}");
+
+    Ok(())
+}
+
+#[test]
+fn incremental_mtime() -> AnyResult<()> {
+    use audiotool::convert::{OutFileAction, SkipReason};
+    use audiotool::convert::config::Incremental;
+    use std::time::{Duration, SystemTime};
+
+    // Filesystems may only record mtimes to the second or worse,
+    // so move them well apart instead of waiting.
+    let set_mtime = |path: &std::path::Path, mtime: SystemTime| {
+        std::fs::File::options().write(true).open(path)?.set_modified(mtime)
+    };
+    let hour = Duration::from_secs(60 * 60);
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Mtime;
+    let (inprops, _) = test_formats();
+    let infile = config.reference_tracks_dir.join("test.wav");
+    set_mtime(&infile, SystemTime::now() - hour)?;
+
+    let results = run_convert(config.clone())?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].action, OutFileAction::Create);
+    assert!(results[0].error.is_ok());
+
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::Skip(SkipReason::UpToDate));
+
+    // Touch the input.
+    write_test_file(&infile, inprops, 1024)?;
+    set_mtime(&infile, SystemTime::now() + hour)?;
+    let results = run_convert(config)?;
+    assert_eq!(results[0].action, OutFileAction::Overwrite);
+    assert!(results[0].error.is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn incremental_hash() -> AnyResult<()> {
+    use audiotool::convert::{OutFileAction, SkipReason};
+    use audiotool::convert::config::Incremental;
+    use audiotool::dsp::DspStage;
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Hash;
+
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::Create);
+
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::Skip(SkipReason::Unchanged));
+
+    // Changing settings triggers a rebuild.
+    config.formats[0].dsp.push(DspStage::Gain { db: -1.0 });
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::Overwrite);
+    assert!(results[0].error.is_ok());
+
+    let results = run_convert(config)?;
+    assert_eq!(results[0].action, OutFileAction::Skip(SkipReason::Unchanged));
+
+    Ok(())
+}
+
+#[test]
+fn incremental_hash_reuses_recorded_hashes() -> AnyResult<()> {
+    use audiotool::convert::{OutFileAction, SkipReason};
+    use audiotool::convert::config::Incremental;
+    use audiotool::convert::manifest::Manifest;
+    use std::time::{Duration, SystemTime};
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Hash;
+    let infile = config.reference_tracks_dir.join("test.wav");
+    let manifest_path = Manifest::path(&config.out_root_dir);
+
+    let results = run_convert(config.clone())?;
+    let mut manifest = Manifest::load(&manifest_path)?;
+    let entry = manifest.outputs.get_mut(&results[0].out_path).expect("entry");
+    let input_hash = entry.input_hash.clone();
+
+    // A recorded hash that can only come from the manifest,
+    // not from hashing the input again.
+    entry.input_hash = Some(S("recorded"));
+    manifest.save(&manifest_path)?;
+
+    let plan = run_plan(config.clone())?;
+    assert_eq!(plan.outputs[0].input_hash.as_deref(), Some("recorded"));
+    assert_eq!(plan.outputs[0].outfiles[0].action(), OutFileAction::Skip(SkipReason::Unchanged));
+
+    // Once the input changes, it is hashed again.
+    std::fs::File::options().write(true).open(&infile)?
+        .set_modified(SystemTime::now() + Duration::from_secs(60 * 60))?;
+    let plan = run_plan(config)?;
+    assert_eq!(plan.outputs[0].input_hash, input_hash);
+    assert_eq!(plan.outputs[0].outfiles[0].action(), OutFileAction::Overwrite);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_input_fails_only_its_outputs() -> AnyResult<()> {
+    use audiotool::convert::config::Incremental;
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_tempdir, mut config) = test_tree(&["good.wav", "unreadable.wav"], 1024)?;
+    config.incremental = Incremental::Hash;
+    let unreadable = config.reference_tracks_dir.join("unreadable.wav");
+    std::fs::set_permissions(&unreadable, std::fs::Permissions::from_mode(0o000))?;
+    if std::fs::File::open(&unreadable).is_ok() {
+        // Permissions don't apply, as for root.
+        return Ok(());
+    }
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 2);
+    for res in results {
+        if res.in_path == unreadable {
+            let e = res.error.expect_err("unreadable input");
+            assert!(format!("{e:#}").contains("error hashing input"));
+        } else {
+            res.error?;
+            assert!(res.out_path.exists());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn manifest_records_outputs() -> AnyResult<()> {
+    use audiotool::convert::manifest::Manifest;
+
+    let (_tempdir, config) = test_tree(&["test.wav"], 4800)?;
+    let (_, outformat) = test_formats();
+    let infile = config.reference_tracks_dir.join("test.wav");
+
+    let results = run_convert(config.clone())?;
+    let manifest = Manifest::load(&Manifest::path(&config.out_root_dir))?;
+    assert_eq!(manifest.outputs.len(), 1);
+
+    let entry = &manifest.outputs[&results[0].out_path];
+    assert!(entry.success);
+    assert_eq!(entry.in_path, infile);
+    assert_eq!(entry.format, outformat);
+    assert!(entry.input_hash.is_some());
+    let stats = entry.stats.expect("stats");
+    assert_eq!(stats.frames, 4800);
+    assert_eq!(stats.duration_secs, 0.1);
+    assert!(stats.peak_dbfs.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn manifest_hashes_reused_while_input_unchanged() -> AnyResult<()> {
+    use audiotool::convert::manifest::Manifest;
+    use audiotool::convert::config::Incremental;
+    use std::time::{Duration, SystemTime};
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Off;
+    let infile = config.reference_tracks_dir.join("test.wav");
+    let manifest_path = Manifest::path(&config.out_root_dir);
+
+    let results = run_convert(config.clone())?;
+    let out_path = &results[0].out_path;
+    let mut manifest = Manifest::load(&manifest_path)?;
+    let entry = manifest.outputs.get_mut(out_path).expect("entry");
+    assert!(entry.input_size.is_some());
+    assert!(entry.input_mtime.is_some());
+    let input_hash = entry.input_hash.clone();
+
+    // A recorded hash that can only come from the manifest,
+    // not from hashing the input again.
+    entry.input_hash = Some(S("recorded"));
+    manifest.save(&manifest_path)?;
+
+    run_convert(config.clone())?;
+    let manifest = Manifest::load(&manifest_path)?;
+    assert_eq!(manifest.outputs[out_path].input_hash.as_deref(), Some("recorded"));
+
+    // Once the input changes, it is hashed again.
+    std::fs::File::options().write(true).open(&infile)?
+        .set_modified(SystemTime::now() + Duration::from_secs(60 * 60))?;
+    run_convert(config)?;
+    let manifest = Manifest::load(&manifest_path)?;
+    assert_eq!(manifest.outputs[out_path].input_hash, input_hash);
+
+    Ok(())
+}
+
+#[test]
+fn clean_removes_only_stale_outputs() -> AnyResult<()> {
+    use audiotool::convert::clean;
+
+    let (_tempdir, config) = test_tree(&["test.wav"], 4800)?;
+    let (_, outformat) = test_formats();
+
+    let results = run_convert(config.clone())?;
+    let expected = &results[0].out_path;
+
+    let stale_dir = config.out_root_dir.join("gone");
+    std::fs::create_dir_all(&stale_dir)?;
+    let stale_file = stale_dir.join(format!("old.{}", outformat.codec.ext()));
+    std::fs::copy(expected, &stale_file)?;
+    let other_file = config.out_root_dir.join("notes.txt");
+    std::fs::write(&other_file, "keep me")?;
+
+    let plan = run_plan(config.clone())?;
+    let stale = clean::stale_outputs(&config, &plan)?;
+    assert_eq!(stale, vec![stale_file.clone()]);
+
+    clean::remove_outputs(&config, &stale)?;
+    assert!(!stale_file.exists());
+    assert!(!stale_dir.exists());
+    assert!(expected.exists());
+    assert!(other_file.exists());
+
+    Ok(())
+}
+
+#[test]
+fn clean_keeps_keep_both_outputs() -> AnyResult<()> {
+    use audiotool::convert::clean;
+    use audiotool::convert::config::Incremental;
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Off;
+    config.overwrite = OverwritePolicy::KeepBoth;
+    run_convert(config.clone())?;
+    let results = run_convert(config.clone())?;
+    let kept = config.out_root_dir.join("test-1.flac");
+    assert_eq!(results[0].out_path, kept);
+
+    // Not made by this tool, so stale.
+    let copy = config.out_root_dir.join("test-9.flac");
+    std::fs::copy(&kept, &copy)?;
+
+    // As `audiotool clean` plans.
+    config.overwrite = OverwritePolicy::Overwrite;
+    let plan = run_plan(config.clone())?;
+    let stale = clean::stale_outputs(&config, &plan)?;
+    assert_eq!(stale, vec![copy.clone()]);
+
+    clean::remove_outputs(&config, &stale)?;
+    assert!(!copy.exists());
+    assert!(kept.exists());
+    assert!(config.out_root_dir.join("test.flac").exists());
+
+    Ok(())
+}
+
+#[test]
+fn readers_report_total_frames() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let (inprops, outformat) = test_formats();
+    let outprops = Props { format: outformat, ..inprops };
+
+    let wav = tempdir.path().join("test.wav");
+    let flac = tempdir.path().join("test.flac");
+    write_test_file(&wav, inprops, 4800)?;
+    write_test_file(&flac, outprops, 4800)?;
+
+    for path in [wav, flac] {
+        let mut reader = audiotool::codecs::reader(&path)?;
+        assert_eq!(reader.total_frames()?, Some(4800));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn readers_honor_block_frames() -> AnyResult<()> {
+    use audiotool::io::Buf;
+
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let (inprops, outformat) = test_formats();
+    let outprops = Props { format: outformat, ..inprops };
+
+    let wav = tempdir.path().join("test.wav");
+    let flac = tempdir.path().join("test.flac");
+    let wav_buf = write_test_file(&wav, inprops, 10_500)?;
+    let flac_buf = write_test_file(&flac, outprops, 10_500)?;
+
+    for (path, expected) in [(wav, wav_buf), (flac, flac_buf)] {
+        let (_, all) = read_file(&path)?;
+        assert_eq!(all, expected);
+
+        let mut reader = audiotool::codecs::reader(&path)?;
+        reader.set_block_frames(1000);
+        let mut buf = Buf::Uninit;
+        let mut block_lens = vec![];
+        loop {
+            reader.read(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            block_lens.push(buf.len() / inprops.channels as usize);
+        }
+        let mut expected_lens = vec![1000; 10];
+        expected_lens.push(500);
+        assert_eq!(block_lens, expected_lens);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn progress_events() -> AnyResult<()> {
+    use audiotool::convert::exec::{self, Response};
+
+    let (_tempdir, config) = test_tree(&["test.wav"], 4800)?;
+    let infile = config.reference_tracks_dir.join("test.wav");
+
+    let plan = run_plan(config)?;
+    let (_tx, rx) = exec::spawn(plan);
+
+    let mut events = vec![];
+    loop {
+        match rx.recv()? {
+            Response::FileStarted { in_path, total_frames } => {
+                assert_eq!(in_path, infile);
+                assert_eq!(total_frames, Some(4800));
+                events.push("started");
+            }
+            Response::Progress { frames, total_frames, .. } => {
+                assert!(frames <= 4800);
+                assert_eq!(total_frames, Some(4800));
+            }
+            Response::FileFinished { in_path } => {
+                assert_eq!(in_path, infile);
+                events.push("finished");
+            }
+            Response::NextResult(res) => {
+                res.error?;
+                events.push("result");
+            }
+            Response::Done => break,
+            Response::Cancelled => panic!(),
+        }
+    }
+
+    assert_eq!(events, ["started", "result", "finished"]);
+
+    Ok(())
+}
+
+#[test]
+fn responses_serialize_as_events() -> AnyResult<()> {
+    use audiotool::convert::exec::{Response, ConvertResult, Cancelled};
+    use audiotool::convert::OutFileAction;
+
+    let (_, format) = test_formats();
+    let result = |error| ConvertResult {
+        in_path: "in/a.wav".into(),
+        out_path: "out/a.flac".into(),
+        format,
+        action: OutFileAction::Create,
+        silence_trim: None,
+        stats: None,
+        error,
+    };
+
+    let ok = Response::NextResult(result(Ok(())));
+    let json = rmx::serde_json::to_value(&ok)?;
+    assert_eq!(json["event"], "result");
+    assert_eq!(json["out_path"], "out/a.flac");
+    assert!(json["error"].is_null());
+
+    let failed = result(Err(anyhow!("disk full").context("writing output")));
+    let json = rmx::serde_json::to_value(Response::NextResult(failed))?;
+    assert_eq!(json["error"]["message"], "writing output");
+    assert_eq!(json["error"]["causes"][0], "disk full");
+
+    let cancelled = result(Err(Cancelled.into()));
+    assert!(cancelled.is_cancelled());
+
+    let json = rmx::serde_json::to_value(Response::Done)?;
+    assert_eq!(json, rmx::serde_json::json!({ "event": "done" }));
+
+    Ok(())
+}
+
+#[test]
+fn bad_input_fails_only_its_outputs() -> AnyResult<()> {
+    let (_tempdir, config) = test_tree(&["good.wav"], 4800)?;
+    let bad = config.reference_tracks_dir.join("bad.wav");
+    std::fs::write(&bad, "not a wav file")?;
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 2);
+    for res in results {
+        if res.in_path == bad {
+            let e = res.error.expect_err("bad input");
+            assert!(format!("{e:#}").contains("error opening input"));
+            assert!(!res.out_path.exists());
+        } else {
+            res.error?;
+            assert!(res.out_path.exists());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_flac_input_fails_only_its_outputs() -> AnyResult<()> {
+    let (_tempdir, mut config) = test_tree(&["good.wav"], 4800)?;
+    config.reference_track_regex = S("\\.(wav|flac)$");
+    let bad = config.reference_tracks_dir.join("bad.flac");
+    let flac_props = Props {
+        channels: 2,
+        format: Format {
+            codec: Codec::Flac,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    };
+    write_test_file(&bad, flac_props, 4800)?;
+
+    // Rewrite the STREAMINFO sample rate, 20 bits from byte 18, to 44.1 kHz.
+    let mut bytes = std::fs::read(&bad)?;
+    assert_eq!(&bytes[..4], b"fLaC");
+    bytes[18] = 0x0A;
+    bytes[19] = 0xC4;
+    bytes[20] = (bytes[20] & 0x0F) | 0x40;
+    std::fs::write(&bad, bytes)?;
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 2);
+    for res in results {
+        if res.in_path == bad {
+            let e = res.error.expect_err("unsupported input");
+            let e = format!("{e:#}");
+            assert!(e.contains("error opening input"));
+            assert!(e.contains("unsupported flac sample rate: 44100 hz"));
+            assert!(!res.out_path.exists());
+        } else {
+            res.error?;
+            assert!(res.out_path.exists());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unwritable_out_dir_fails_only_its_outputs() -> AnyResult<()> {
+    let (_tempdir, config) = test_tree(&["good/test.wav", "blocked/test.wav"], 4800)?;
+    let blocked = config.reference_tracks_dir.join("blocked/test.wav");
+
+    // A file where the output directory should be.
+    std::fs::create_dir_all(&config.out_root_dir)?;
+    std::fs::write(config.out_root_dir.join("blocked"), "")?;
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 2);
+    for res in results {
+        if res.in_path == blocked {
+            let e = res.error.expect_err("blocked output");
+            assert!(format!("{e:#}").contains("creating directory"));
+        } else {
+            res.error?;
+            assert!(res.out_path.exists());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_writer_props_are_errors() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let flac_f32 = Props {
+        channels: 2,
+        format: Format {
+            codec: Codec::Flac,
+            bit_depth: BitDepth::F32,
+            sample_rate: SampleRate::K48,
+        },
+    };
+    let wav_6ch = Props {
+        channels: 6,
+        format: Format {
+            codec: Codec::Wav,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    };
+
+    for props in [flac_f32, wav_6ch] {
+        let path = tempdir.path().join(format!("out.{}", props.format.codec.ext()));
+        let settings = EncoderSettings::default_for(props.format.codec);
+        assert!(audiotool::codecs::writer(&path, props, &settings).is_err());
+        assert!(!path.exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_output_format_fails_its_output() -> AnyResult<()> {
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 4800)?;
+    config.formats = vec![Format {
+        codec: Codec::Flac,
+        bit_depth: BitDepth::F32,
+        sample_rate: SampleRate::K48,
+    }.into()];
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 1);
+    let e = results[0].error.as_ref().expect_err("flac f32");
+    assert!(format!("{e:#}").contains("unsupported output"));
+    assert!(!results[0].out_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn single_file() -> AnyResult<()> {
+    use audiotool::convert::plan;
+
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let (inprops, _) = test_formats();
+    let infile = tempdir.path().join("in.wav");
+    let outfile = tempdir.path().join("sub/out.flac");
+    write_test_file(&infile, inprops, 4800)?;
+
+    let plan = plan::single_file(&infile, &outfile, Some(BitDepth::I16), None, OverwritePolicy::Overwrite)?;
+    assert!(plan.manifest_path.is_none());
+    let results = run_exec(plan)?;
+    assert_eq!(results.len(), 1);
+    results.into_iter().next().expect("result").error?;
+
+    let (outprops, _) = read_file(&outfile)?;
+    assert_eq!(outprops, Props {
+        channels: 2,
+        format: Format {
+            codec: Codec::Flac,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    });
+
+    // Defaults come from the input.
+    let plan = plan::single_file(&infile, &tempdir.path().join("copy.wav"), None, None, OverwritePolicy::Overwrite)?;
+    assert_eq!(plan.outputs[0].outfiles[0].format(), inprops.format);
+
+    assert!(plan::single_file(&infile, &tempdir.path().join("out.mp3"), None, None, OverwritePolicy::Overwrite).is_err());
+
+    // FLAC can't store floats, which is caught before converting.
+    let e = plan::single_file(&infile, &outfile, Some(BitDepth::F32), None, OverwritePolicy::Overwrite)
+        .expect_err("flac f32");
+    assert!(e.to_string().contains("unsupported output"), "{e}");
+
+    Ok(())
+}
+
+#[test]
+fn encoder_settings() -> AnyResult<()> {
+    use audiotool::convert::config::FormatConfig;
+    use audiotool::codecs::FlacSettings;
+
+    let (_, outformat) = test_formats();
+
+    let parse = |toml: &str| -> AnyResult<EncoderSettings> {
+        let mut format_config = FormatConfig::from(outformat);
+        format_config.encoder = rmx::toml::from_str(toml)?;
+        format_config.encoder_settings()
+    };
+
+    assert_eq!(parse("")?, EncoderSettings::Flac(FlacSettings::default()));
+    assert_eq!(parse("compression_level = 8\nblocksize = 1024")?, EncoderSettings::Flac(FlacSettings {
+        compression_level: 8,
+        blocksize: Some(1024),
+        ..FlacSettings::default()
+    }));
+
+    let e = parse("quality = 0.5").expect_err("vorbis key for flac");
+    assert!(format!("{e:#}").contains("unknown field `quality`"));
+    let e = parse("compression_level = 9").expect_err("out of range");
+    assert!(format!("{e:#}").contains("compression_level"));
+
+    // Padding shows up in the written file.
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 4800)?;
+    config.formats[0].encoder = rmx::toml::from_str("padding = 1234\nverify = true")?;
+
+    let results = run_convert(config.clone())?;
+    let info = audiotool::info::file_info(&results[0].out_path)?;
+    assert!(info.chunks.iter().any(|chunk| chunk.id == "PADDING" && chunk.size == 1234));
+
+    // Bad settings fail the plan.
+    config.formats[0].encoder = rmx::toml::from_str("bogus = 1")?;
+    let (_tx, rx) = audiotool::convert::plan::spawn(config);
+    let audiotool::convert::plan::Response::Done(res) = rx.recv()?;
+    assert!(res.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn per_format_out_path_templates() -> AnyResult<()> {
+    use audiotool::convert::config::FormatConfig;
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    let (inprops, _) = test_formats();
+    let mut wav_format = FormatConfig::from(inprops.format);
+    wav_format.out_path_template = Some(S(
+        "{{out_root_dir}}/{{codec}}-{{sample_rate}}-{{bit_depth}}-{{channels}}ch/\
+         {{tags.artist | default(value=\"unknown\")}}/{{file_stem}}.{{format_ext}}"
+    ));
+    config.formats.push(wav_format);
+
+    let plan = run_plan(config.clone())?;
+    let outfiles = &plan.outputs[0].outfiles;
+    assert_eq!(outfiles[0].path(), config.out_root_dir.join("./test.flac"));
+    assert_eq!(
+        outfiles[1].path(),
+        config.out_root_dir.join(format!(
+            "wav-{}-{}-{}ch/unknown/test.wav",
+            inprops.format.sample_rate.as_u32(),
+            inprops.format.bit_depth.bits(),
+            inprops.channels,
+        )),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn out_path_template_errors_fail_only_their_outputs() -> AnyResult<()> {
+    use audiotool::convert::config::FormatConfig;
+
+    let (_tempdir, mut config) = test_tree(&["good.wav", "tagged.wav"], 1024)?;
+    let (inprops, _) = test_formats();
+    let bad = config.reference_tracks_dir.join("bad.wav");
+    std::fs::write(&bad, "not a wav file")?;
+    config.out_path_template = S("{{out_root_dir}}/{{channels}}ch/{{file_stem}}.{{format_ext}}");
+    let mut wav_format = FormatConfig::from(inprops.format);
+    wav_format.out_path_template = Some(S("{{out_root_dir}}/{{tags.artist}}/{{file_stem}}.{{format_ext}}"));
+    config.formats.push(wav_format);
+
+    let tagged = config.reference_tracks_dir.join("tagged.wav");
+    append_riff_info(&tagged, &[(b"IART", "Someone")])?;
+
+    let plan = run_plan(config.clone())?;
+    assert_eq!(plan.outputs.len(), 3);
+    for infile_plan in &plan.outputs {
+        let errors: Vec<_> = infile_plan.outfiles.iter()
+            .map(|outfile| outfile.error())
+            .collect();
+        if infile_plan.infile == bad {
+            // Can't probe the input, so neither output has a path.
+            assert!(errors.iter().all(|error| error.unwrap().contains("error probing input")));
+        } else if infile_plan.infile == tagged {
+            assert_eq!(errors, [None, None]);
+        } else {
+            // No artist tag.
+            assert_eq!(errors[0], None);
+            assert!(errors[1].is_some());
+        }
+    }
+
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 6);
+    let failed = results.iter().filter(|res| res.error.is_err()).count();
+    assert_eq!(failed, 3);
+    for res in results.iter().filter(|res| res.in_path == tagged) {
+        assert!(res.error.is_ok());
+        assert!(res.out_path.exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn inputs_only_opened_for_referenced_vars() -> AnyResult<()> {
+    let (_tempdir, mut config) = test_tree(&[], 1024)?;
+    // A WAV file whose format and tags can't be read.
+    let bad = config.reference_tracks_dir.join("bad.wav");
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend(100_u32.to_le_bytes());
+    bytes.extend(b"WAVELIST");
+    bytes.extend(u32::MAX.to_le_bytes());
+    std::fs::write(&bad, bytes)?;
+
+    // Mentioning the names outside of expressions doesn't open the input.
+    config.out_path_template = S(
+        "{{out_root_dir}}/channels-tags/{# tags #}{{file_stem | replace(from=\"channels\", to=\"tags\")}}.{{format_ext}}"
+    );
+    let plan = run_plan(config.clone())?;
+    assert_eq!(plan.outputs[0].outfiles[0].error(), None);
+
+    // Referring to them in any expression does.
+    for template in [
+        "{{out_root_dir}}/{% if channels == 2 %}stereo{% endif %}/{{file_stem}}.{{format_ext}}",
+        "{{out_root_dir}}/{{file_stem | default(value=tags.artist)}}.{{format_ext}}",
+        "{{out_root_dir}}/{% for key, value in tags %}{{key}}{% endfor %}/{{file_stem}}.{{format_ext}}",
+    ] {
+        config.out_path_template = S(template);
+        let plan = run_plan(config.clone())?;
+        let error = plan.outputs[0].outfiles[0].error().expect(template);
+        assert!(error.contains("error probing input") || error.contains("error reading tags"), "{error}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tags_in_out_paths_are_single_components() -> AnyResult<()> {
+    let cases = [
+        ("a/b\\c", "a_b_c"),
+        ("..", "__"),
+        (".", "_"),
+        ("...", "___"),
+        ("", "_"),
+        ("..x", "..x"),
+    ];
+    let inputs: Vec<_> = (0..cases.len()).map(|i| format!("{i}.wav")).collect();
+    let inputs: Vec<_> = inputs.iter().map(String::as_str).collect();
+    let (_tempdir, mut config) = test_tree(&inputs, 1024)?;
+    config.out_path_template = S("{{out_root_dir}}/{{tags.artist}}/{{file_stem}}.{{format_ext}}");
+    for (i, (artist, _)) in cases.iter().enumerate() {
+        let infile = config.reference_tracks_dir.join(format!("{i}.wav"));
+        append_riff_info(&infile, &[(b"IART", artist)])?;
+    }
+
+    let plan = run_plan(config.clone())?;
+    for infile_plan in &plan.outputs {
+        let stem = infile_plan.infile.file_stem().unwrap().to_str().unwrap();
+        let (_, dir) = cases[stem.parse::<usize>()?];
+        let outfile = &infile_plan.outfiles[0];
+        assert_eq!(outfile.error(), None);
+        assert_eq!(outfile.path(), config.out_root_dir.join(dir).join(format!("{stem}.flac")));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn output_collisions_fail_the_plan() -> AnyResult<()> {
+    use audiotool::convert::plan;
+
+    let plan_err = |config| -> String {
+        let (_tx, rx) = plan::spawn(config);
+        let plan::Response::Done(res) = rx.recv().expect("recv");
+        format!("{:#}", res.expect_err("collision"))
+    };
+
+    let (_tempdir, config) = test_tree(&["test.wav"], 1024)?;
+    let (inprops, outformat) = test_formats();
+    let infile = config.reference_tracks_dir.join("test.wav");
+
+    // Two formats differing only in something the template ignores.
+    let mut two_formats = config.clone();
+    two_formats.formats.push(Format {
+        bit_depth: BitDepth::I24,
+        ..outformat
+    }.into());
+    let e = plan_err(two_formats);
+    assert!(e.contains("1 output path collisions"), "{e}");
+    assert!(e.contains("test.flac collides with"), "{e}");
+
+    // Writing back into the input tree.
+    let mut in_place = config.clone();
+    in_place.out_root_dir = in_place.reference_tracks_dir.clone();
+    in_place.formats = vec![inprops.format.into()];
+    let e = plan_err(in_place);
+    assert!(e.contains("overwrites input"), "{e}");
+
+    assert!(plan::single_file(&infile, &infile, None, None, OverwritePolicy::Overwrite).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn overwrite_policy() -> AnyResult<()> {
+    use audiotool::convert::{plan, OutFileAction, SkipReason};
+    use audiotool::convert::config::Incremental;
+
+    let (_tempdir, mut config) = test_tree(&["test.wav"], 1024)?;
+    config.incremental = Incremental::Off;
+    let outfile = config.out_root_dir.join("test.flac");
+
+    // Nothing exists yet, so no policy applies.
+    config.overwrite = OverwritePolicy::Error;
+    run_convert(config.clone())?;
+    assert!(outfile.exists());
+
+    let (_tx, rx) = plan::spawn(config.clone());
+    let plan::Response::Done(res) = rx.recv()?;
+    let e = format!("{:#}", res.expect_err("exists"));
+    assert!(e.contains("1 outputs already exist"), "{e}");
+
+    config.overwrite = OverwritePolicy::Skip;
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::Skip(SkipReason::Exists));
+
+    config.overwrite = OverwritePolicy::KeepBoth;
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].action, OutFileAction::KeepBoth);
+    assert_eq!(results[0].out_path, config.out_root_dir.join("./test-1.flac"));
+    assert!(results[0].error.is_ok());
+    let results = run_convert(config.clone())?;
+    assert_eq!(results[0].out_path, config.out_root_dir.join("./test-2.flac"));
+
+    config.overwrite = OverwritePolicy::Overwrite;
+    let results = run_convert(config)?;
+    assert_eq!(results[0].action, OutFileAction::Overwrite);
+
+    Ok(())
+}
+
+#[test]
+fn multiple_input_roots() -> AnyResult<()> {
+    use audiotool::convert::config::InputRoot;
+
+    let (tempdir, mut config) = test_tree(&[], 0)?;
+    let (inprops, _) = test_formats();
+    let other_dir = tempdir.path().join("other");
+    config.reference_track_regex = S("");
+    config.include = vec![S("*.wav")];
+    config.exclude = vec![S("drafts/")];
+    config.inputs = vec![InputRoot {
+        dir: other_dir.clone(),
+        out_dir: "more/stuff".into(),
+        include: vec![],
+        exclude: vec![S("*.tmp.wav"), S("!keep.tmp.wav")],
+    }];
+
+    let in_dir = &config.reference_tracks_dir;
+    for path in [
+        in_dir.join("a.wav"),
+        in_dir.join("sub/b.wav"),
+        in_dir.join("drafts/c.wav"),
+        other_dir.join("d.wav"),
+        other_dir.join("sub/e.tmp.wav"),
+        other_dir.join("keep.tmp.wav"),
+    ] {
+        std::fs::create_dir_all(path.parent().expect("parent"))?;
+        write_test_file(&path, inprops, 16)?;
+    }
+    std::fs::write(in_dir.join("notes.txt"), "")?;
+
+    let plan = run_plan(config.clone())?;
+    let mut outfiles: Vec<_> = plan.outputs.iter()
+        .flat_map(|infile_plan| &infile_plan.outfiles)
+        .map(|outfile| outfile.path().strip_prefix(&config.out_root_dir).expect("prefix").to_owned())
+        .collect();
+    outfiles.sort();
+
+    let expected: Vec<std::path::PathBuf> = vec![
+        "a.flac".into(),
+        "more/stuff/d.flac".into(),
+        "more/stuff/keep.tmp.flac".into(),
+        "sub/b.flac".into(),
+    ];
+    assert_eq!(outfiles, expected);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_and_non_utf8_paths() -> AnyResult<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    let (tempdir, mut config) = test_tree(&[], 0)?;
+    let (inprops, outformat) = test_formats();
+    config.follow_symlinks = true;
+    let in_dir = config.reference_tracks_dir.clone();
+    let other_dir = tempdir.path().join("other");
+    std::fs::create_dir_all(&other_dir)?;
+
+    // Latin-1 "café.wav".
+    let latin1_name = OsStr::from_bytes(b"caf\xe9.wav");
+    write_test_file(&in_dir.join(latin1_name), inprops, 1024)?;
+    write_test_file(&other_dir.join("linked.wav"), inprops, 1024)?;
+    symlink(&other_dir, in_dir.join("album"))?;
+    // A cycle, which must not be walked forever.
+    symlink(&in_dir, in_dir.join("loop"))?;
+
+    let results = run_convert(config.clone())?;
+    assert_eq!(results.len(), 2);
+    for result in &results {
+        assert!(result.error.is_ok(), "{:?}", result.error);
+    }
+
+    let (outprops, _) = read_file(&config.out_root_dir.join(OsStr::from_bytes(b"caf\xe9.flac")))?;
+    assert_eq!(outprops.format, outformat);
+    assert!(config.out_root_dir.join("album/linked.flac").exists());
+
+    Ok(())
+}
+
+#[test]
+fn plan_for_inputs() -> AnyResult<()> {
+    use audiotool::convert::{plan, OutFileAction};
+
+    let (tempdir, config) = test_tree(&["old.wav"], 1024)?;
+    let (inprops, _) = test_formats();
+    let in_dir = &config.reference_tracks_dir;
+    run_convert(config.clone())?;
+
+    let new_file = in_dir.join("new.wav");
+    write_test_file(&new_file, inprops, 1024)?;
+    let outside = tempdir.path().join("outside.wav");
+    write_test_file(&outside, inprops, 1024)?;
+    std::fs::write(in_dir.join("notes.txt"), "")?;
+
+    let plan = plan::for_inputs(&config, &[
+        new_file.clone(),
+        outside,
+        in_dir.join("notes.txt"),
+        in_dir.join("deleted.wav"),
+    ])?;
+    assert_eq!(plan.outputs.len(), 1);
+    assert_eq!(plan.outputs[0].infile, new_file);
+    assert_eq!(plan.outputs[0].outfiles[0].action(), OutFileAction::Create);
+
+    let results = run_exec(plan)?;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].error.is_ok());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn plan_for_inputs_under_relative_root() -> AnyResult<()> {
+    use audiotool::convert::plan;
+    use std::path::{Component, PathBuf};
+
+    let (_tempdir, mut config) = test_tree(&[], 1024)?;
+    let (inprops, _) = test_formats();
+    let new_file = config.reference_tracks_dir.join("new.wav");
+    write_test_file(&new_file, inprops, 1024)?;
+
+    // The input root relative to the working directory, as in configs.
+    let cwd = std::env::current_dir()?;
+    let mut relative_root: PathBuf = cwd.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative_root.push(config.reference_tracks_dir.strip_prefix("/")?);
+    let relative_root = PathBuf::from(".").join(relative_root);
+    config.reference_tracks_dir = relative_root.clone();
+
+    // Watchers report absolute paths, in various forms.
+    let event_paths = [
+        cwd.join(&relative_root).join("new.wav"),
+        std::fs::canonicalize(&new_file)?,
+    ];
+    for event_path in event_paths {
+        let plan = plan::for_inputs(&config, &[event_path])?;
+        assert_eq!(plan.outputs.len(), 1);
+        assert_eq!(plan.outputs[0].infile, relative_root.join("new.wav"));
+        assert_eq!(plan.outputs[0].outfiles[0].error(), None);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn exec_limits() -> AnyResult<()> {
+    use audiotool::convert::config::Limits;
+
+    let (_tempdir, mut config) = test_tree(&["0.wav", "1.wav", "2.wav", "3.wav"], 1024)?;
+
+    let limits: Limits = rmx::toml::from_str("jobs = 2\nmax_buffer_memory = 1")?;
+    assert_eq!(limits.jobs.map(|jobs| jobs.get()), Some(2));
+    assert!(rmx::toml::from_str::<Limits>("jobs = 0").is_err());
+
+    // Too small a budget for any file still converts one at a time.
+    config.limits = limits;
+    let results = run_convert(config)?;
+    assert_eq!(results.len(), 4);
+    assert!(results.iter().all(|result| result.error.is_ok()));
+
+    Ok(())
+}
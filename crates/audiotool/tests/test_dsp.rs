@@ -0,0 +1,109 @@
+use audiotool::types::SampleRate;
+use audiotool::io::Buf;
+use audiotool::dsp::*;
+
+fn run_chain(stages: &[DspStage], channels: u16, input: Vec<f32>, block: usize) -> Vec<f32> {
+    run_chain_report(stages, channels, input, block).0
+}
+
+fn run_chain_report(stages: &[DspStage], channels: u16, input: Vec<f32>, block: usize) -> (Vec<f32>, DspReport) {
+    let mut chain = DspChain::new(stages, channels, SampleRate::K48);
+    let mut output = vec![];
+    for chunk in input.chunks(block * channels as usize) {
+        let inbuf = Buf::F32(chunk.to_vec());
+        match chain.process(&inbuf) {
+            Buf::F32(buf) => output.extend_from_slice(buf),
+            _ => panic!(),
+        }
+    }
+    let (buf, report) = chain.finish();
+    match buf {
+        Buf::F32(buf) => output.extend_from_slice(buf),
+        _ => panic!(),
+    }
+    (output, report)
+}
+
+#[test]
+fn empty_chain_passes_through() {
+    let input: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+    let output = run_chain(&[], 2, input.clone(), 7);
+    assert_eq!(input, output);
+}
+
+#[test]
+fn gain() {
+    let input = vec![0.5; 64];
+    let output = run_chain(&[DspStage::Gain { db: -6.0 }], 1, input, 16);
+    for sample in output {
+        assert!((sample - 0.2506).abs() < 0.001);
+    }
+}
+
+#[test]
+fn trim_head_and_tail() {
+    let input: Vec<f32> = (0..200).map(|i| i as f32).collect();
+    let stages = [DspStage::Trim {
+        head: Length::Samples(10),
+        tail: Length::Samples(20),
+    }];
+    // Blocks smaller than the trim lengths, in stereo.
+    let output = run_chain(&stages, 2, input.clone(), 3);
+    assert_eq!(&input[20..160], &output[..]);
+}
+
+#[test]
+fn fade_in_and_out() {
+    let input = vec![1.0; 100];
+    let stages = [
+        DspStage::FadeIn { length: Length::Samples(10), curve: FadeCurve::Linear },
+        DspStage::FadeOut { length: Length::Samples(10), curve: FadeCurve::EqualPower },
+    ];
+    let output = run_chain(&stages, 1, input, 4);
+    assert_eq!(output.len(), 100);
+    assert_eq!(output[0], 0.0);
+    assert!(output[5] > 0.4 && output[5] < 0.6);
+    assert_eq!(&output[10..90], &[1.0; 80][..]);
+    assert!(output[95] < 0.8);
+    assert_eq!(output[99], 0.0);
+}
+
+#[test]
+fn remove_dc() {
+    let input = vec![0.25; 48_000];
+    let output = run_chain(&[DspStage::RemoveDc], 1, input, 4096);
+    assert!(output[47_999].abs() < 0.001);
+}
+
+#[test]
+fn trim_silence() {
+    let mut input = vec![0.0; 100];
+    input.extend([0.5, 0.0, 0.0, -0.5]);
+    input.extend([0.00001; 50]);
+    let stages = [DspStage::TrimSilence {
+        threshold_db: -60.0,
+        pad: Length::Samples(3),
+    }];
+    let (output, report) = run_chain_report(&stages, 1, input.clone(), 8);
+    assert_eq!(&input[97..107], &output[..]);
+    assert_eq!(report.silence_trim, Some(SilenceTrim {
+        start_frame: 97,
+        end_frame: 107,
+        total_frames: 154,
+    }));
+}
+
+#[test]
+fn trim_silence_all_silent() {
+    let stages = [DspStage::TrimSilence {
+        threshold_db: -60.0,
+        pad: Length::Samples(3),
+    }];
+    let (output, report) = run_chain_report(&stages, 2, vec![0.0; 100], 8);
+    assert!(output.is_empty());
+    assert_eq!(report.silence_trim, Some(SilenceTrim {
+        start_frame: 50,
+        end_frame: 50,
+        total_frames: 50,
+    }));
+}
@@ -0,0 +1,129 @@
+use rmx::prelude::*;
+use audiotool::types::*;
+use audiotool::io::Props;
+use audiotool::codecs::Container;
+use audiotool::info::{self, Tag};
+use audiotool::testsupport::*;
+
+fn test_props(codec: Codec) -> Props {
+    Props {
+        channels: 2,
+        format: Format {
+            codec,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    }
+}
+
+fn tag(key: &str, value: &str) -> Tag {
+    Tag { key: S(key), value: S(value) }
+}
+
+/// Write a test WAV file with a LIST/INFO chunk appended,
+/// returning its bytes.
+fn write_wav_with_info(path: &std::path::Path, props: Props) -> AnyResult<Vec<u8>> {
+    write_test_file(path, props, 4800)?;
+    append_riff_info(path, &[(b"IART", "Someone"), (b"INAM", "A Song")])
+}
+
+#[test]
+fn wav_info() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.wav");
+    let props = test_props(Codec::Wav);
+    let bytes = write_wav_with_info(&path, props)?;
+
+    let info = info::file_info(&path)?;
+    assert_eq!(info.container, Container::Wav);
+    assert_eq!(info.props, props);
+    assert_eq!(info.frames, Some(4800));
+    assert_eq!(info.duration_secs, Some(0.1));
+    assert_eq!(info.file_size, bytes.len() as u64);
+    assert_eq!(info.md5, None);
+    assert_eq!(info.tags, vec![tag("artist", "Someone"), tag("title", "A Song")]);
+    let ids: Vec<_> = info.chunks.iter().map(|chunk| chunk.id.as_str()).collect();
+    assert_eq!(ids, ["fmt ", "data", "LIST"]);
+
+    Ok(())
+}
+
+#[test]
+fn wav_list_chunk_past_end_of_file() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.wav");
+    let props = test_props(Codec::Wav);
+    let bytes = write_wav_with_info(&path, props)?;
+    let list_offset = bytes.len() - 4 - 16 - 16 - 8;
+    assert_eq!(&bytes[list_offset..list_offset + 4], b"LIST");
+
+    // Truncated partway through the LIST chunk.
+    std::fs::write(&path, &bytes[..bytes.len() - 10])?;
+    let e = info::read_tags(&path).expect_err("truncated");
+    assert!(e.to_string().contains("past the end of the file"));
+    assert!(info::file_info(&path).is_err());
+
+    // A LIST chunk claiming far more than the file holds.
+    let mut bytes = bytes;
+    bytes[list_offset + 4..list_offset + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&path, &bytes)?;
+    let e = info::read_tags(&path).expect_err("oversized");
+    assert!(e.to_string().contains("past the end of the file"));
+
+    Ok(())
+}
+
+#[test]
+fn flac_info() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.flac");
+    let props = test_props(Codec::Flac);
+    write_test_file(&path, props, 4800)?;
+
+    // Insert a VORBIS_COMMENT block after STREAMINFO,
+    // which is never the last block here.
+    let mut bytes = std::fs::read(&path)?;
+    let mut comments = vec![];
+    comments.extend(4_u32.to_le_bytes());
+    comments.extend(b"test");
+    comments.extend(1_u32.to_le_bytes());
+    let comment = b"ARTIST=Someone";
+    comments.extend((comment.len() as u32).to_le_bytes());
+    comments.extend(comment);
+    let mut block = vec![4];
+    block.extend(&(comments.len() as u32).to_be_bytes()[1..]);
+    block.extend(&comments);
+    let streaminfo_end = 4 + 4 + 34;
+    bytes.splice(streaminfo_end..streaminfo_end, block);
+    std::fs::write(&path, &bytes)?;
+
+    let info = info::file_info(&path)?;
+    assert_eq!(info.container, Container::Flac);
+    assert_eq!(info.props, props);
+    assert_eq!(info.frames, Some(4800));
+    assert!(info.md5.is_some());
+    assert_eq!(info.md5_verified, Some(true));
+    assert!(info.tags.contains(&tag("artist", "Someone")));
+    assert_eq!(info.chunks[0].id, "STREAMINFO");
+    assert_eq!(info.chunks[0].size, 34);
+    assert_eq!(info.chunks[1].id, "VORBIS_COMMENT");
+
+    Ok(())
+}
+
+#[test]
+fn flac_md5_mismatch() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.flac");
+    write_test_file(&path, test_props(Codec::Flac), 4800)?;
+
+    // The MD5 is the last 16 bytes of STREAMINFO.
+    let mut bytes = std::fs::read(&path)?;
+    bytes[8 + 18] ^= 0xff;
+    std::fs::write(&path, &bytes)?;
+
+    let info = info::file_info(&path)?;
+    assert_eq!(info.md5_verified, Some(false));
+
+    Ok(())
+}
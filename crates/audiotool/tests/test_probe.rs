@@ -0,0 +1,139 @@
+use rmx::prelude::*;
+use audiotool::types::*;
+use audiotool::io::{Props, Buf};
+use audiotool::codecs::{self, Container, OggCodec};
+use audiotool::testsupport::*;
+use std::path::Path;
+
+fn test_props(codec: Codec) -> Props {
+    Props {
+        channels: 2,
+        format: Format {
+            codec,
+            bit_depth: BitDepth::I16,
+            sample_rate: SampleRate::K48,
+        },
+    }
+}
+
+fn detect_bytes(dir: &Path, bytes: &[u8]) -> AnyResult<Option<Container>> {
+    let path = dir.join("file");
+    std::fs::write(&path, bytes)?;
+    codecs::detect_container(&path)
+}
+
+#[test]
+fn probe_ignores_misleading_extension() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let props = test_props(Codec::Flac);
+    let flac = tempdir.path().join("test.flac");
+    write_test_file(&flac, props, 1024)?;
+
+    let misnamed = tempdir.path().join("test.wav");
+    std::fs::rename(&flac, &misnamed)?;
+    assert_eq!(codecs::probe(&misnamed)?, props);
+
+    let (read_props, buf) = read_file(&misnamed)?;
+    assert_eq!(read_props, props);
+    assert_eq!(buf.len(), 2048);
+
+    Ok(())
+}
+
+#[test]
+fn probe_extensionless() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let props = test_props(Codec::Wav);
+    let wav = tempdir.path().join("test.wav");
+    write_test_file(&wav, props, 1024)?;
+
+    let extensionless = tempdir.path().join("test");
+    std::fs::rename(&wav, &extensionless)?;
+    assert_eq!(codecs::probe(&extensionless)?, props);
+
+    Ok(())
+}
+
+#[test]
+fn probe_unsupported_container() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.wav");
+    std::fs::write(&path, b"FORM\0\0\0\x04AIFF")?;
+    let e = codecs::probe(&path).expect_err("aiff");
+    assert!(e.to_string().contains("AIFF"));
+
+    Ok(())
+}
+
+#[test]
+fn read_i24_wav_in_4_byte_samples() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let path = tempdir.path().join("test.wav");
+    let spec = hound::WavSpecEx {
+        spec: hound::WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        },
+        bytes_per_sample: 4,
+    };
+    let samples = [0, 1, -1, (1 << 23) - 1, -(1 << 23), 12345];
+    let mut writer = hound::WavWriter::new_with_spec_ex(std::fs::File::create(&path)?, spec)?;
+    for sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+
+    let (props, buf) = read_file(&path)?;
+    assert_eq!(props.format.bit_depth, BitDepth::I24);
+    match buf {
+        Buf::I24(buf) => assert_eq!(buf, samples),
+        _ => panic!("not i24"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn detect_magic_bytes() -> AnyResult<()> {
+    let tempdir = rmx::tempfile::TempDir::with_prefix("audiotool")?;
+    let dir = tempdir.path();
+
+    let ogg_page = |packet: &[u8]| {
+        let mut page = b"OggS".to_vec();
+        page.extend([0; 22]);
+        // One segment.
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend(packet);
+        page
+    };
+
+    let mut id3_flac = b"ID3\x04\0\0\0\0\0\x05".to_vec();
+    id3_flac.extend([0; 5]);
+    id3_flac.extend(b"fLaC");
+
+    let cases: &[(&[u8], Option<Container>)] = &[
+        (b"RIFF\0\0\0\0WAVEfmt ", Some(Container::Wav)),
+        (b"RF64\xff\xff\xff\xffWAVEds64", Some(Container::Rf64)),
+        (b"fLaC\0\0\0\x22", Some(Container::Flac)),
+        (&ogg_page(b"\x01vorbis"), Some(Container::Ogg(OggCodec::Vorbis))),
+        (&ogg_page(b"OpusHead"), Some(Container::Ogg(OggCodec::Opus))),
+        (&ogg_page(b"\x7fFLAC"), Some(Container::Ogg(OggCodec::Flac))),
+        (&ogg_page(b"theora"), Some(Container::Ogg(OggCodec::Unknown))),
+        (b"FORM\0\0\0\0AIFC", Some(Container::Aiff)),
+        (b"ID3\x03\0\0\0\0\0\0\xff\xfb", Some(Container::Mpeg)),
+        (&id3_flac, Some(Container::Flac)),
+        (b"\xff\xfb\x90\x00", Some(Container::Mpeg)),
+        (b"\0\0\0\x20ftypM4A ", Some(Container::Mp4)),
+        (b"hello", None),
+        (b"", None),
+    ];
+
+    for (bytes, expected) in cases {
+        assert_eq!(&detect_bytes(dir, bytes)?, expected, "{bytes:?}");
+    }
+
+    Ok(())
+}